@@ -0,0 +1,167 @@
+use crate::database::DatabaseArgs;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use pointguard_engine_postgres::{self as db, postgres::PgPool, ArchivedTask};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+/// How many tasks are moved from the database to the archive at once
+const ARCHIVE_BATCH_SIZE: i64 = 1000;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Db(db::Error),
+    Io(std::io::Error),
+}
+
+impl Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveError::Db(err) => write!(f, "database error: {err}"),
+            ArchiveError::Io(err) => write!(f, "io error: {err}"),
+        }
+    }
+}
+
+impl From<db::Error> for ArchiveError {
+    fn from(err: db::Error) -> Self {
+        Self::Db(err)
+    }
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Moves the finished tasks created before `before` into gzipped JSON Lines files in `dir`,
+/// one file per day and batch (`finished_tasks-YYYY-MM-DD-<first id>.jsonl.gz`).
+///
+/// Every file is written in full and synced to disk before the batch is deleted from
+/// the database, so a crash leaves either no file or a complete one, never a truncated file.
+/// If the delete doesn't commit, the batch is archived again under the same name.
+/// Returns how many tasks were archived.
+pub async fn archive_finished_tasks(
+    db: &PgPool,
+    dir: &Path,
+    before: DateTime<Utc>,
+) -> Result<usize, ArchiveError> {
+    std::fs::create_dir_all(dir)?;
+    let mut archived = 0;
+
+    loop {
+        let mut tx = db.begin().await?;
+        let tasks = db::take_finished_tasks_to_archive(&mut tx, before, ARCHIVE_BATCH_SIZE).await?;
+        if tasks.is_empty() {
+            break;
+        }
+        archived += tasks.len();
+
+        let dir = dir.to_path_buf();
+        tokio::task::spawn_blocking(move || write_archive(&dir, tasks))
+            .await
+            .expect("archive writer panicked")?;
+
+        tx.commit().await?;
+    }
+
+    Ok(archived)
+}
+
+fn write_archive(dir: &Path, tasks: Vec<ArchivedTask>) -> std::io::Result<()> {
+    let mut by_day: BTreeMap<NaiveDate, Vec<ArchivedTask>> = BTreeMap::new();
+    for task in tasks {
+        by_day
+            .entry(task.created_at.date_naive())
+            .or_default()
+            .push(task);
+    }
+
+    for (day, tasks) in by_day {
+        let first_id = tasks.iter().map(|task| task.id).min().unwrap_or_default();
+        let name = format!(
+            "finished_tasks-{}-{first_id}.jsonl.gz",
+            day.format("%Y-%m-%d")
+        );
+        // written aside and renamed, so the file is only ever seen complete
+        let tmp_path = dir.join(format!(".{name}.tmp"));
+        let mut encoder = GzEncoder::new(File::create(&tmp_path)?, Compression::default());
+        for task in tasks {
+            serde_json::to_writer(&mut encoder, &task)?;
+            encoder.write_all(b"\n")?;
+        }
+        encoder.finish()?.sync_all()?;
+        std::fs::rename(&tmp_path, dir.join(name))?;
+    }
+    // makes the renames durable
+    File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+pub struct Archive {
+    #[clap(subcommand)]
+    command: ArchiveCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ArchiveCommand {
+    /// Load archive files into the `archived_tasks` table for investigation
+    Import {
+        #[clap(flatten)]
+        database: DatabaseArgs,
+
+        /// The archive files to import (.jsonl or .jsonl.gz)
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+impl Archive {
+    pub async fn call(self) {
+        match self.command {
+            ArchiveCommand::Import { database, files } => {
+                let pool = database.connect().await;
+                for file in files {
+                    let tasks = read_archive(&file)
+                        .unwrap_or_else(|err| panic!("reading archive {}: {err}", file.display()));
+                    let inserted = db::import_archived_tasks(&pool, &tasks)
+                        .await
+                        .expect("importing archived tasks");
+                    tracing::info!(
+                        file = %file.display(),
+                        "imported {inserted} of {} archived tasks",
+                        tasks.len()
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn read_archive(path: &Path) -> std::io::Result<Vec<ArchivedTask>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn BufRead> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let mut tasks = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        tasks.push(serde_json::from_str(&line)?);
+    }
+    Ok(tasks)
+}
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EndpointCredentials {
+    credentials: Vec<EndpointCredential>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EndpointCredential {
+    /// The endpoint URLs this applies to, like `https://api.example.com/jobs/`
+    prefix: String,
+    /// Sent as `Authorization: Bearer <token>`
+    bearer_token: Option<String>,
+    /// Sent as `Authorization: Basic ...`
+    basic: Option<BasicAuth>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct BasicAuth {
+    username: String,
+    password: Option<String>,
+}
+
+/// Keeps the secrets out of the logs
+impl std::fmt::Debug for EndpointCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EndpointCredential")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EndpointCredentials {
+    /// The credential with the longest prefix matching the endpoint
+    pub fn for_endpoint(&self, endpoint: &str) -> Option<&EndpointCredential> {
+        self.credentials
+            .iter()
+            .filter(|credential| credential.matches(endpoint))
+            .max_by_key(|credential| credential.prefix.len())
+    }
+}
+
+impl EndpointCredential {
+    /// Prefixes only match whole path segments, so `https://api.example.com`
+    /// doesn't match `https://api.example.com.evil.com`
+    fn matches(&self, endpoint: &str) -> bool {
+        match endpoint.strip_prefix(&self.prefix) {
+            None => false,
+            Some(rest) => {
+                self.prefix.ends_with('/') || rest.is_empty() || rest.starts_with(['/', '?', '#'])
+            }
+        }
+    }
+
+    pub fn authenticate(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match (&self.bearer_token, &self.basic) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some(basic)) => request.basic_auth(&basic.username, basic.password.as_ref()),
+            (None, None) => unreachable!("credentials are validated when they are loaded"),
+        }
+    }
+}
+
+/// Reads an endpoint credentials file, which looks like:
+///
+/// ```json
+/// {
+///   "credentials": [
+///     { "prefix": "https://api.example.com/", "bearerToken": "..." },
+///     { "prefix": "https://legacy.example.com/", "basic": { "username": "...", "password": "..." } }
+///   ]
+/// }
+/// ```
+pub fn load(path: &Path) -> EndpointCredentials {
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        panic!(
+            "reading endpoint credentials file {}: {err}",
+            path.display()
+        )
+    });
+    let file: EndpointCredentials = serde_json::from_str(&contents).unwrap_or_else(|err| {
+        panic!(
+            "parsing endpoint credentials file {}: {err}",
+            path.display()
+        )
+    });
+
+    for credential in &file.credentials {
+        assert!(
+            credential.bearer_token.is_some() != credential.basic.is_some(),
+            "endpoint credential for {:?} needs exactly one of bearerToken or basic",
+            credential.prefix
+        );
+    }
+
+    file
+}
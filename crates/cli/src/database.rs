@@ -0,0 +1,149 @@
+use pointguard_engine_postgres as db;
+use std::path::PathBuf;
+
+/// Used for read replicas when `--database-acquire-timeout` isn't provided
+const REPLICA_ACQUIRE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Postgres identifiers are at most 63 bytes
+const MAX_SCHEMA_LENGTH: usize = 63;
+
+/// Listeners hold their connections for good, so leave at least one for queries
+fn parse_max_connections(value: &str) -> Result<u32, String> {
+    let max_connections = value.parse::<u32>().map_err(|err| err.to_string())?;
+    let min = db::LISTENER_CONNECTIONS + 1;
+    if max_connections < min {
+        return Err(format!(
+            "at least {min} are needed, {} of them are held for listening to notifications",
+            db::LISTENER_CONNECTIONS
+        ));
+    }
+    Ok(max_connections)
+}
+
+/// Schemas are created unquoted, which folds them to lowercase, but looked up as they are
+/// in `search_path`, so only names that read the same both ways are allowed.
+/// Longer names would be truncated by Postgres, which could put two tenants in the same schema.
+pub fn parse_schema(schema: &str) -> Result<String, String> {
+    let mut chars = schema.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && schema.len() <= MAX_SCHEMA_LENGTH;
+    if !valid {
+        return Err(format!(
+            "schema names are up to {MAX_SCHEMA_LENGTH} lowercase letters, digits and '_', \
+            and don't start with a digit, got {schema:?}"
+        ));
+    }
+    Ok(schema.to_string())
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DatabaseArgs {
+    /// A PostgreSQL connnection string to use.
+    #[clap(long, env = "DATABASE_URL")]
+    pub database_url: String,
+
+    /// A database schema to use, like "pointguard"
+    #[clap(long = "database-schema", env = "DATABASE_SCHEMA", value_parser = parse_schema)]
+    pub schema: Option<String>,
+
+    /// The maximum number of database connections to open (defaults to 10).
+    /// `serve` keeps 3 of them for listening to notifications, per tenant too,
+    /// so it needs at least 4.
+    #[clap(
+        long = "database-max-connections",
+        env = "DATABASE_MAX_CONNECTIONS",
+        value_parser = parse_max_connections,
+        verbatim_doc_comment
+    )]
+    pub max_connections: Option<u32>,
+
+    /// The number of database connections to keep open even when idle
+    #[clap(long = "database-min-connections", env = "DATABASE_MIN_CONNECTIONS")]
+    pub min_connections: Option<u32>,
+
+    /// How long to wait for a free database connection, e.g. "5s" (defaults to 30s)
+    #[clap(long = "database-acquire-timeout", env = "DATABASE_ACQUIRE_TIMEOUT")]
+    pub acquire_timeout: Option<humantime::Duration>,
+
+    /// How long a database connection can stay idle before it is closed, e.g. "5m" (defaults to 10m)
+    #[clap(long = "database-idle-timeout", env = "DATABASE_IDLE_TIMEOUT")]
+    pub idle_timeout: Option<humantime::Duration>,
+
+    /// Abort any statement that takes longer than this, e.g. "30s".
+    /// Sets `statement_timeout` on every connection. Migrations are not limited.
+    #[clap(
+        long = "database-statement-timeout",
+        env = "DATABASE_STATEMENT_TIMEOUT",
+        verbatim_doc_comment
+    )]
+    pub statement_timeout: Option<humantime::Duration>,
+
+    /// Abort any statement that waits longer than this for a lock, e.g. "5s".
+    /// Sets `lock_timeout` on every connection.
+    #[clap(
+        long = "database-lock-timeout",
+        env = "DATABASE_LOCK_TIMEOUT",
+        verbatim_doc_comment
+    )]
+    pub lock_timeout: Option<humantime::Duration>,
+
+    /// How to secure the database connection.
+    /// One of: disable, allow, prefer, require, verify-ca, verify-full.
+    /// Overrides the `sslmode` of the connection string.
+    #[clap(
+        long = "database-ssl-mode",
+        env = "DATABASE_SSL_MODE",
+        verbatim_doc_comment
+    )]
+    pub ssl_mode: Option<db::postgres::PgSslMode>,
+
+    /// A PEM file with the CA certificate to verify the database server with
+    #[clap(long = "database-ssl-root-cert", env = "DATABASE_SSL_ROOT_CERT")]
+    pub ssl_root_cert: Option<PathBuf>,
+
+    /// A PEM file with the client certificate to authenticate to the database with
+    #[clap(long = "database-ssl-client-cert", env = "DATABASE_SSL_CLIENT_CERT")]
+    pub ssl_client_cert: Option<PathBuf>,
+
+    /// A PEM file with the private key of the client certificate
+    #[clap(long = "database-ssl-client-key", env = "DATABASE_SSL_CLIENT_KEY")]
+    pub ssl_client_key: Option<PathBuf>,
+}
+
+impl DatabaseArgs {
+    pub fn db_options(&self) -> db::DbOptions {
+        db::DbOptions {
+            schema: self.schema.clone(),
+            max_connections: self.max_connections,
+            min_connections: self.min_connections,
+            acquire_timeout: self.acquire_timeout.map(Into::into),
+            idle_timeout: self.idle_timeout.map(Into::into),
+            statement_timeout: self.statement_timeout.map(Into::into),
+            lock_timeout: self.lock_timeout.map(Into::into),
+            ssl_mode: self.ssl_mode,
+            ssl_root_cert: self.ssl_root_cert.clone(),
+            ssl_client_cert: self.ssl_client_cert.clone(),
+            ssl_client_key: self.ssl_client_key.clone(),
+        }
+    }
+
+    pub async fn connect(&self) -> db::postgres::PgPool {
+        db::connect(&self.database_url, &self.db_options())
+            .await
+            .expect("connecting to the database")
+    }
+
+    /// Connects to a read replica of this database, sharing the pool, session and TLS options.
+    /// The connection is made lazily, so an unreachable replica doesn't stop the server from starting.
+    pub fn connect_replica(&self, url: &str) -> db::postgres::PgPool {
+        let mut options = self.db_options();
+        // don't hold requests for long before falling back to the primary
+        options
+            .acquire_timeout
+            .get_or_insert(REPLICA_ACQUIRE_TIMEOUT);
+        db::connect_lazy(url, &options)
+    }
+}
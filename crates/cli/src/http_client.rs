@@ -0,0 +1,44 @@
+use pointguard_web_api::EndpointPolicy;
+use std::sync::Arc;
+
+/// How many redirects we follow, like reqwest's default policy
+const MAX_REDIRECTS: usize = 10;
+
+/// A client that can only reach the endpoints the policy allows,
+/// even when DNS records change after a task was enqueued or an endpoint redirects
+pub fn new(policy: Arc<EndpointPolicy>) -> reqwest::Client {
+    let redirect_policy = policy.clone();
+    reqwest::Client::builder()
+        .dns_resolver(Arc::new(PolicyResolver(policy)))
+        // a proxy would resolve endpoints itself, where the resolver can't check them
+        .no_proxy()
+        .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            // redirects to a domain are checked by the resolver
+            match redirect_policy.allows(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(reason) => attempt.error(format!("redirect not allowed: {reason}")),
+            }
+        }))
+        .build()
+        .expect("building the HTTP client")
+}
+
+/// Resolves hosts the way the system does, refusing the ones with private addresses
+struct PolicyResolver(Arc<EndpointPolicy>);
+
+impl reqwest::dns::Resolve for PolicyResolver {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        let policy = self.0.clone();
+        Box::pin(async move {
+            let addresses: Vec<_> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if addresses.iter().any(|a| !policy.allows_address(a.ip())) {
+                return Err(format!("{} resolves to a private address", name.as_str()).into());
+            }
+            let addresses: reqwest::dns::Addrs = Box::new(addresses.into_iter());
+            Ok(addresses)
+        })
+    }
+}
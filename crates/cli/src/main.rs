@@ -1,11 +1,21 @@
+mod archive;
+mod credentials;
+mod database;
+mod http_client;
+mod maintenance;
+mod migrate;
+mod signing;
 mod task_loop;
+mod tenants;
 mod tracing_config;
 
 use clap::{Parser, Subcommand};
 use futures::future::FutureExt;
 use pointguard_engine_postgres as db;
-use pointguard_web_api::Server;
-use std::fmt::Display;
+use pointguard_web_api::{
+    parse_invocation_timeout, EndpointPattern, EndpointPolicy, Server, Tenant,
+};
+use std::{fmt::Display, num::NonZeroUsize, path::PathBuf, sync::Arc};
 
 #[tracing::instrument(skip_all, fields(%host, %port))]
 pub fn print_welcome_message(host: impl Display, port: impl Display) {
@@ -37,10 +47,18 @@ struct Cli {
 }
 
 #[derive(Debug, Subcommand)]
+// parsed once on startup, so the size of `Serve` doesn't matter
+#[allow(clippy::large_enum_variant)]
 enum Command {
     /// Run the web server
     Serve(Serve),
 
+    /// Manage the database migrations
+    Migrate(migrate::Migrate),
+
+    /// Work with finished tasks archives
+    Archive(archive::Archive),
+
     /// Print the OpenAPI spec
     #[clap(name = "openapi-spec")]
     OpenApiSpec(OpenApiSpec),
@@ -48,9 +66,14 @@ enum Command {
 
 #[derive(Parser, Debug)]
 struct Serve {
-    /// A PostgreSQL connnection string to use.
-    #[clap(long, env = "DATABASE_URL")]
-    database_url: String,
+    #[clap(flatten)]
+    database: database::DatabaseArgs,
+
+    /// A PostgreSQL connection string for a read replica.
+    /// The enqueued and finished task listings are served from it,
+    /// falling back to the primary database when it can't be reached.
+    #[clap(long, env = "READ_DATABASE_URL", verbatim_doc_comment)]
+    read_database_url: Option<String>,
 
     /// The host to bind to.
     ///
@@ -63,47 +86,367 @@ struct Serve {
     #[clap(long, env = "PORT", default_value = "8080")]
     port: u16,
 
+    /// How many tasks can be running at once, per tenant.
+    /// New tasks are only claimed when there is room for them.
+    #[clap(long, env = "CONCURRENCY", default_value = "100", verbatim_doc_comment)]
+    concurrency: NonZeroUsize,
+
+    /// The most tasks to claim from the database at a time
+    #[clap(long, env = "BATCH_SIZE", default_value = "5")]
+    batch_size: NonZeroUsize,
+
+    /// How long to wait for running tasks to finish on shutdown, e.g. "30s".
+    /// Tasks still running after that are released back to the queue
+    /// without counting as a retry.
+    #[clap(
+        long,
+        env = "DRAIN_TIMEOUT",
+        default_value = "30s",
+        verbatim_doc_comment
+    )]
+    drain_timeout: humantime::Duration,
+
+    /// How long to wait for an endpoint to respond to an invocation, e.g. "5m", from 1ms to 24h.
+    /// Timed out invocations are retried. Tasks can set their own `timeout`.
+    #[clap(
+        long,
+        env = "INVOCATION_TIMEOUT",
+        default_value = "5m",
+        value_parser = parse_invocation_timeout,
+        verbatim_doc_comment
+    )]
+    invocation_timeout: std::time::Duration,
+
+    /// Overrides the invocation timeout for a job, as "JOB=DURATION", e.g. "send-email=30s".
+    /// Can be given multiple times, or comma separated in the environment variable.
+    #[clap(
+        long = "job-timeout",
+        env = "JOB_TIMEOUTS",
+        value_delimiter = ',',
+        value_parser = parse_job_timeout,
+        verbatim_doc_comment
+    )]
+    job_timeouts: Vec<(String, std::time::Duration)>,
+
+    /// Jobs whose endpoints don't respond like pointguard endpoints, so any 2xx response
+    /// counts as a success. Tasks can set their own `responseMode`.
+    /// Can be given multiple times, or comma separated in the environment variable.
+    #[clap(
+        long = "lenient-job",
+        env = "LENIENT_JOBS",
+        value_delimiter = ',',
+        verbatim_doc_comment
+    )]
+    lenient_jobs: Vec<String>,
+
+    /// Jobs whose endpoints are retried when they respond with a 4xx status.
+    /// By default only 408 and 429 responses are, since a bad request stays bad,
+    /// but some endpoints respond with 404 or 409 until they are ready.
+    /// Can be given multiple times, or comma separated in the environment variable.
+    #[clap(
+        long = "retriable-4xx-job",
+        env = "RETRIABLE_4XX_JOBS",
+        value_delimiter = ',',
+        verbatim_doc_comment
+    )]
+    retriable_4xx_jobs: Vec<String>,
+
+    /// How many times an endpoint can reschedule a task, by responding with `reschedule`,
+    /// before the task fails. Rescheduling doesn't count as a retry.
+    #[clap(long, env = "MAX_SNOOZES", default_value = "100", verbatim_doc_comment)]
+    max_snoozes: u32,
+
+    /// A JSON file with credentials to invoke endpoints with, by URL prefix,
+    /// so secrets don't have to be stored with every task.
+    /// They are sent to matching endpoints of every tenant.
+    #[clap(long, env = "ENDPOINT_CREDENTIALS_FILE", verbatim_doc_comment)]
+    endpoint_credentials_file: Option<PathBuf>,
+
+    /// Secrets to sign invocations with, like "whsec_<base64>".
+    /// Requests get Standard Webhooks `webhook-id`, `webhook-timestamp` and `webhook-signature` headers.
+    /// Give more than one while rotating secrets, comma separated in the environment variable.
+    #[clap(
+        long = "signing-secret",
+        env = "SIGNING_SECRETS",
+        value_delimiter = ',',
+        hide_env_values = true,
+        verbatim_doc_comment
+    )]
+    signing_secrets: Vec<signing::SigningSecret>,
+
+    /// Keys to encrypt the headers tasks are enqueued with, 32 bytes base64 encoded,
+    /// like `openssl rand -base64 32` prints. Tasks can't have headers without one.
+    /// The first key encrypts, and every key is tried to decrypt, so a key can be rotated
+    /// by putting the new one first. Comma separated in the environment variable.
+    #[clap(
+        long = "header-encryption-key",
+        env = "HEADER_ENCRYPTION_KEYS",
+        value_delimiter = ',',
+        hide_env_values = true,
+        verbatim_doc_comment
+    )]
+    header_encryption_keys: Vec<db::HeaderKey>,
+
+    /// Endpoints tasks can be sent to, like "https://*.example.com" or "http://localhost:3000".
+    /// The scheme and port are optional. If not provided, any endpoint on a public address is allowed.
+    /// Can be given multiple times, or comma separated in the environment variable.
+    #[clap(
+        long = "allowed-endpoint",
+        env = "ALLOWED_ENDPOINTS",
+        value_delimiter = ',',
+        verbatim_doc_comment
+    )]
+    allowed_endpoints: Vec<EndpointPattern>,
+
+    /// Allow endpoints on loopback, private and link-local addresses, e.g. for local development.
+    /// They are rejected by default, so tasks can't reach internal services like cloud metadata.
+    #[clap(long, env = "ALLOW_PRIVATE_ENDPOINTS", verbatim_doc_comment)]
+    allow_private_endpoints: bool,
+
     /// Run migrations on startup,
     /// if the database schema is not up to date.
     #[clap(long = "migrate")]
     should_migrate: bool,
 
-    /// A database schema to use
-    #[clap(long = "database-schema", env = "DATABASE_SCHEMA")]
-    schema: Option<String>,
+    /// A JSON file with additional tenants to serve from this process.
+    /// Each tenant is mapped to a database schema and gets its own
+    /// pool, task loop and URL prefix (/t/:name/api/v1/...).
+    #[clap(long, env = "TENANTS_FILE", verbatim_doc_comment)]
+    tenants_file: Option<PathBuf>,
+
+    /// How long to keep finished tasks for, e.g. "30d".
+    /// Expired tasks are dropped a day at a time. If not provided, they are kept forever.
+    #[clap(long, env = "FINISHED_TASKS_RETENTION", verbatim_doc_comment)]
+    finished_tasks_retention: Option<humantime::Duration>,
+
+    /// A directory to archive finished tasks to before they are deleted.
+    /// Tasks are written as gzipped JSON Lines, one file per day and batch,
+    /// and can be loaded back with `pointguard archive import`.
+    #[clap(long, env = "ARCHIVE_DIR", verbatim_doc_comment)]
+    archive_dir: Option<PathBuf>,
+
+    /// How old finished tasks should be before they are archived, e.g. "7d".
+    /// Only used with --archive-dir. Tasks are always archived before the retention drops them.
+    #[clap(
+        long,
+        env = "ARCHIVE_AFTER",
+        default_value = "7d",
+        verbatim_doc_comment
+    )]
+    archive_after: humantime::Duration,
 }
 
 impl Serve {
-    async fn call(self) {
-        let db_options = db::DbOptions {
-            schema: self.schema,
-        };
-        let pool = db::connect(&self.database_url, &db_options).await.unwrap();
+    async fn call(self) -> Result<(), PendingMigrations> {
+        let pool = self.prepare_database(&self.database).await?;
 
-        if self.should_migrate {
-            db::migrate(&pool, &db_options)
-                .await
-                .expect("running migrations");
+        let tenant_configs = self
+            .tenants_file
+            .as_deref()
+            .map(tenants::load)
+            .unwrap_or_default();
+        let mut tenant_pools = Vec::with_capacity(tenant_configs.len());
+        for config in tenant_configs {
+            let database = config.database_args(&self.database);
+            let read_pool = config
+                .read_database_url(self.read_database_url.as_deref())
+                .map(|url| database.connect_replica(url));
+            tenant_pools.push((
+                config.name,
+                self.prepare_database(&database).await?,
+                read_pool,
+            ));
         }
 
+        let credentials = Arc::new(
+            self.endpoint_credentials_file
+                .as_deref()
+                .map(credentials::load)
+                .unwrap_or_default(),
+        );
+
+        let endpoint_policy = Arc::new(EndpointPolicy {
+            allowed: self.allowed_endpoints.clone(),
+            allow_private_networks: self.allow_private_endpoints,
+        });
+
         let termination = shutdown_signal().shared();
         let (events_tx, events_rx) = flume::unbounded();
-        let task_loop = task_loop::run(pool.clone(), termination.clone(), events_tx.clone());
+        let task_listener = db::ListenerHealth::default();
+        let mut task_loops = vec![task_loop::run(
+            pool.clone(),
+            termination.clone(),
+            events_tx.clone(),
+            task_listener.clone(),
+            self.task_loop_options(&credentials, &endpoint_policy),
+        )];
+        let mut maintenance_loops = vec![maintenance::run(
+            pool.clone(),
+            termination.clone(),
+            self.maintenance_options(None),
+        )];
+
+        let mut tenants = Vec::with_capacity(tenant_pools.len());
+        for (name, pool, read_pool) in tenant_pools {
+            let (events_tx, events_rx) = flume::unbounded();
+            let task_listener = db::ListenerHealth::default();
+            task_loops.push(task_loop::run(
+                pool.clone(),
+                termination.clone(),
+                events_tx.clone(),
+                task_listener.clone(),
+                self.task_loop_options(&credentials, &endpoint_policy),
+            ));
+            maintenance_loops.push(maintenance::run(
+                pool.clone(),
+                termination.clone(),
+                self.maintenance_options(Some(&name)),
+            ));
+            tracing::info!(tenant = %name, "serving tenant at /t/{name}");
+            tenants.push(Tenant {
+                name,
+                pool,
+                read_pool,
+                task_listener,
+                events: (events_tx, events_rx),
+            });
+        }
 
         let serving = Server {
+            read_pool: self
+                .read_database_url
+                .as_deref()
+                .map(|url| self.database.connect_replica(url)),
             pool,
+            task_listener,
+            tenants,
+            endpoint_policy,
+            header_keys: self.header_encryption_keys.clone().into(),
             host: self.host,
             port: self.port,
             on_bind: Box::new(|host, port| print_welcome_message(host, port)),
         }
         .serve(termination, (events_tx, events_rx));
 
-        tokio::join!(task_loop, serving);
+        tokio::join!(
+            futures::future::join_all(task_loops),
+            futures::future::join_all(maintenance_loops),
+            serving
+        );
 
         tracing::info!("goodbye!");
+        Ok(())
+    }
+
+    fn task_loop_options(
+        &self,
+        credentials: &Arc<credentials::EndpointCredentials>,
+        endpoint_policy: &Arc<EndpointPolicy>,
+    ) -> task_loop::Options {
+        task_loop::Options {
+            concurrency: self.concurrency.get(),
+            batch_size: self.batch_size.get(),
+            drain_timeout: self.drain_timeout.into(),
+            invocation_timeout: self.invocation_timeout,
+            job_timeouts: self
+                .job_timeouts
+                .iter()
+                .map(|(job_name, timeout)| (job_name.clone(), *timeout))
+                .collect(),
+            lenient_jobs: self.lenient_jobs.iter().cloned().collect(),
+            retriable_4xx_jobs: self.retriable_4xx_jobs.iter().cloned().collect(),
+            max_snoozes: self.max_snoozes,
+            credentials: credentials.clone(),
+            signing_secrets: self.signing_secrets.clone(),
+            header_keys: self.header_encryption_keys.clone(),
+            endpoint_policy: endpoint_policy.clone(),
+        }
+    }
+
+    /// Tenants archive to their own sub directory
+    fn maintenance_options(&self, tenant: Option<&str>) -> maintenance::Options {
+        maintenance::Options {
+            finished_tasks_retention: self.finished_tasks_retention.map(Into::into),
+            archive: self
+                .archive_dir
+                .as_ref()
+                .map(|dir| maintenance::ArchiveOptions {
+                    dir: match tenant {
+                        Some(tenant) => dir.join(tenant),
+                        None => dir.clone(),
+                    },
+                    after: self.archive_after.into(),
+                }),
+        }
+    }
+
+    async fn prepare_database(
+        &self,
+        database: &database::DatabaseArgs,
+    ) -> Result<db::postgres::PgPool, PendingMigrations> {
+        let pool = database.connect().await;
+
+        if self.should_migrate {
+            db::migrate(&pool, &database.db_options())
+                .await
+                .expect("running migrations");
+        } else {
+            ensure_migrated(&pool, database).await?;
+        }
+
+        Ok(pool)
     }
 }
 
+fn parse_job_timeout(value: &str) -> Result<(String, std::time::Duration), String> {
+    let (job_name, timeout) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected JOB=DURATION, got {value:?}"))?;
+    let timeout = parse_invocation_timeout(timeout).map_err(|err| format!("{job_name}: {err}"))?;
+    Ok((job_name.to_string(), timeout))
+}
+
+/// The database schema is behind this binary, so we can't serve it
+#[derive(Debug)]
+struct PendingMigrations {
+    schema: String,
+    count: usize,
+}
+
+impl Display for PendingMigrations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the {} database schema is behind this version of pointguard ({} pending migrations). \
+            run `pointguard migrate up` or start the server with `--migrate`.",
+            self.schema, self.count
+        )
+    }
+}
+
+/// Refuses to start when the database is missing migrations this binary relies on.
+async fn ensure_migrated(
+    pool: &db::postgres::PgPool,
+    database: &database::DatabaseArgs,
+) -> Result<(), PendingMigrations> {
+    let pending = db::pending_migrations(pool)
+        .await
+        .expect("reading migration status");
+
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let schema = database.schema.as_deref().unwrap_or("default");
+    for migration in &pending {
+        tracing::error!(version = %migration.version, %schema, "pending migration: {}", migration.description);
+    }
+    Err(PendingMigrations {
+        schema: schema.to_string(),
+        count: pending.len(),
+    })
+}
+
 #[tokio::main]
 async fn main() {
     let config = Cli::parse();
@@ -111,7 +454,14 @@ async fn main() {
     tracing_config::init(&config.tracing_format);
 
     match config.subcommand {
-        Command::Serve(serve) => serve.call().await,
+        Command::Serve(serve) => {
+            if let Err(err) = serve.call().await {
+                tracing::error!("{err}");
+                std::process::exit(1);
+            }
+        }
+        Command::Migrate(migrate) => migrate.call().await,
+        Command::Archive(archive) => archive.call().await,
         Command::OpenApiSpec(spec) => spec.call(),
     }
 }
@@ -0,0 +1,80 @@
+use crate::archive;
+use futures::Future;
+use pointguard_engine_postgres::{self as db, postgres::PgPool};
+use std::{path::PathBuf, time::Duration};
+
+/// How often we look after the finished tasks partitions
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How many days of partitions we create in advance
+const PARTITIONS_AHEAD: u32 = 7;
+
+pub struct Options {
+    /// Drop finished tasks older than this. `None` keeps them forever.
+    pub finished_tasks_retention: Option<Duration>,
+    pub archive: Option<ArchiveOptions>,
+}
+
+pub struct ArchiveOptions {
+    pub dir: PathBuf,
+    /// Archive finished tasks older than this
+    pub after: Duration,
+}
+
+impl Options {
+    /// Archive anything the retention is about to drop, even if it isn't `after` old yet
+    fn archive_before(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        let archive = self.archive.as_ref()?;
+        let age = match self.finished_tasks_retention {
+            Some(retention) => archive.after.min(retention),
+            None => archive.after,
+        };
+        let age = chrono::Duration::from_std(age).unwrap_or(chrono::Duration::max_value());
+        chrono::Utc::now().checked_sub_signed(age)
+    }
+}
+
+pub async fn run(db: PgPool, termination: impl Future<Output = ()>, options: Options) {
+    tokio::pin!(termination);
+
+    loop {
+        let mut retention = options.finished_tasks_retention;
+
+        if let (Some(archive), Some(before)) = (&options.archive, options.archive_before()) {
+            match archive::archive_finished_tasks(&db, &archive.dir, before).await {
+                Ok(0) => {}
+                Ok(count) => {
+                    tracing::info!(dir = %archive.dir.display(), "archived {count} finished tasks")
+                }
+                Err(err) => {
+                    tracing::error!("can't archive finished tasks: {err}");
+                    // don't drop partitions that might hold tasks we failed to archive
+                    retention = None;
+                }
+            }
+        }
+
+        match db::maintain_finished_tasks_partitions(&db, PARTITIONS_AHEAD, retention).await {
+            Ok(Some(maintenance)) => {
+                for partition in maintenance.created {
+                    tracing::info!(%partition, "created finished tasks partition");
+                }
+                for partition in maintenance.dropped {
+                    tracing::info!(%partition, "dropped expired finished tasks partition");
+                }
+                if maintenance.pruned > 0 {
+                    tracing::info!(
+                        "deleted {} expired finished tasks from the default partition",
+                        maintenance.pruned
+                    );
+                }
+            }
+            Ok(None) => tracing::debug!("another worker is maintaining the partitions"),
+            Err(err) => tracing::error!("can't maintain finished tasks partitions: {err}"),
+        }
+
+        tokio::select! {
+            _ = &mut termination => break,
+            _ = tokio::time::sleep(MAINTENANCE_INTERVAL) => {}
+        }
+    }
+}
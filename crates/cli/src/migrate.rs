@@ -0,0 +1,64 @@
+use crate::database::DatabaseArgs;
+use clap::{Parser, Subcommand};
+use pointguard_engine_postgres as db;
+
+#[derive(Parser, Debug)]
+pub struct Migrate {
+    #[clap(flatten)]
+    database: DatabaseArgs,
+
+    #[clap(subcommand)]
+    command: MigrateCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum MigrateCommand {
+    /// Apply all pending migrations
+    Up,
+
+    /// Revert applied migrations
+    Down {
+        /// Revert every migration newer than this version.
+        /// If not provided, only the latest applied migration is reverted.
+        #[clap(long, verbatim_doc_comment)]
+        target: Option<i64>,
+    },
+
+    /// List the migrations and whether they were applied
+    Status,
+}
+
+impl Migrate {
+    pub async fn call(self) {
+        let pool = self.database.connect().await;
+
+        match self.command {
+            MigrateCommand::Up => {
+                db::migrate(&pool, &self.database.db_options())
+                    .await
+                    .expect("running migrations");
+                tracing::info!("database is up to date");
+            }
+            MigrateCommand::Down { target } => {
+                let reverted = db::revert_migrations(&pool, target)
+                    .await
+                    .expect("reverting migrations");
+                if reverted.is_empty() {
+                    tracing::info!("nothing to revert");
+                }
+                for version in reverted {
+                    tracing::info!(%version, "reverted migration");
+                }
+            }
+            MigrateCommand::Status => {
+                let statuses = db::migration_status(&pool)
+                    .await
+                    .expect("reading migration status");
+                for status in statuses {
+                    let state = if status.applied { "applied" } else { "pending" };
+                    println!("{}\t{state}\t{}", status.version, status.description);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::str::FromStr;
+
+/// Signs invocations following Standard Webhooks (https://www.standardwebhooks.com),
+/// so endpoints can tell they come from pointguard
+#[derive(Clone)]
+pub struct SigningSecret(Vec<u8>);
+
+/// Keeps the secret out of the logs
+impl std::fmt::Debug for SigningSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SigningSecret(..)")
+    }
+}
+
+impl FromStr for SigningSecret {
+    type Err = String;
+
+    /// Secrets look like `whsec_<base64>`, like other Standard Webhooks implementations
+    fn from_str(secret: &str) -> Result<Self, Self::Err> {
+        let encoded = secret
+            .strip_prefix("whsec_")
+            .ok_or("signing secrets start with whsec_")?;
+        let key = STANDARD
+            .decode(encoded)
+            .map_err(|err| format!("signing secret is not valid base64: {err}"))?;
+        if key.is_empty() {
+            return Err("signing secret is empty".to_string());
+        }
+        Ok(Self(key))
+    }
+}
+
+impl SigningSecret {
+    /// Signs `{id}.{timestamp}.{body}`
+    fn sign(&self, id: &str, timestamp: i64, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC takes keys of any size");
+        mac.update(format!("{id}.{timestamp}.").as_bytes());
+        mac.update(body);
+        format!("v1,{}", STANDARD.encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// The signature header value. Signing with every secret lets endpoints
+/// verify with either the old or the new one while a secret is rotated.
+pub fn signature(secrets: &[SigningSecret], id: &str, timestamp: i64, body: &[u8]) -> String {
+    secrets
+        .iter()
+        .map(|secret| secret.sign(id, timestamp, body))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example from the Standard Webhooks specification
+    #[test]
+    fn signs_like_the_specification() {
+        let secret: SigningSecret = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw".parse().unwrap();
+        let signature = signature(
+            &[secret],
+            "msg_p5jXN8AQM9LWM0D4loKWxJek",
+            1614265330,
+            br#"{"test": 2432232314}"#,
+        );
+        assert_eq!(signature, "v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=");
+    }
+
+    #[test]
+    fn signs_with_every_secret() {
+        let old: SigningSecret = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw".parse().unwrap();
+        let new: SigningSecret = "whsec_c2VjcmV0".parse().unwrap();
+        let signature = signature(
+            &[new, old],
+            "msg_p5jXN8AQM9LWM0D4loKWxJek",
+            1614265330,
+            br#"{"test": 2432232314}"#,
+        );
+        let signatures: Vec<_> = signature.split(' ').collect();
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(
+            signatures[1],
+            "v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE="
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_secrets() {
+        assert!("MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw"
+            .parse::<SigningSecret>()
+            .is_err());
+        assert!("whsec_not base64".parse::<SigningSecret>().is_err());
+        assert!("whsec_".parse::<SigningSecret>().is_err());
+    }
+}
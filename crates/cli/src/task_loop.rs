@@ -1,6 +1,89 @@
-use futures::Future;
+use crate::{credentials::EndpointCredentials, http_client, signing};
+use futures::{Future, FutureExt};
 use pointguard_engine_postgres::{self as db, postgres::PgPool};
-use pointguard_types::{Event, InvokedTaskPayload, InvokedTaskResponse};
+use pointguard_types::{
+    headers, CancelledTaskPayload, Event, InvokedTaskPayload, InvokedTaskResponse, RescheduleAt,
+};
+use pointguard_web_api::EndpointPolicy;
+use reqwest::StatusCode;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::{oneshot, watch, Semaphore},
+    task::JoinSet,
+};
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// How many tasks can be running at once
+    pub concurrency: usize,
+    /// The most tasks to claim in a single query
+    pub batch_size: usize,
+    /// How long to wait for running tasks on shutdown before releasing them
+    pub drain_timeout: std::time::Duration,
+    /// How long to wait for an endpoint to respond, unless the job or task says otherwise
+    pub invocation_timeout: std::time::Duration,
+    /// Invocation timeouts by job name
+    pub job_timeouts: HashMap<String, std::time::Duration>,
+    /// Jobs whose endpoints can respond with anything, unless the task says otherwise
+    pub lenient_jobs: HashSet<String>,
+    /// Jobs whose endpoints are retried when they respond with a 4xx status
+    pub retriable_4xx_jobs: HashSet<String>,
+    /// How many times a task can be rescheduled by its endpoint before it fails
+    pub max_snoozes: u32,
+    /// Authenticates invocations by endpoint URL prefix
+    pub credentials: Arc<EndpointCredentials>,
+    /// Invocations are signed with each of these, if there are any
+    pub signing_secrets: Vec<signing::SigningSecret>,
+    /// Decrypt the headers tasks were enqueued with
+    pub header_keys: Vec<db::HeaderKey>,
+    /// Which endpoints tasks can be sent to
+    pub endpoint_policy: Arc<EndpointPolicy>,
+}
+
+impl Options {
+    /// The task's own timeout, then its job's, then the default
+    fn timeout_for(&self, task: &db::InflightTask) -> std::time::Duration {
+        task.timeout()
+            .or_else(|| self.job_timeouts.get(&task.job_name).copied())
+            .unwrap_or(self.invocation_timeout)
+    }
+
+    /// The task's own response mode, then its job's
+    fn response_mode_for(&self, task: &db::InflightTask) -> db::ResponseMode {
+        task.response_mode().unwrap_or_else(|| {
+            if self.lenient_jobs.contains(&task.job_name) {
+                db::ResponseMode::Lenient
+            } else {
+                db::ResponseMode::Strict
+            }
+        })
+    }
+}
+
+/// The running tasks, by ID, and how to cancel them
+type Cancellations = Arc<Mutex<HashMap<i64, oneshot::Sender<()>>>>;
+
+/// How much of an error response we keep in the task's error message
+const MAX_ERROR_BODY: usize = 1024;
+
+/// The longest we honor a `Retry-After` for
+const MAX_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// How long we wait for a cancel endpoint to respond
+const CANCEL_ENDPOINT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often we look for accepted tasks whose lease expired
+const LEASE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long we sleep when there are no tasks, in case we missed a notification
+const MAX_IDLE: std::time::Duration = std::time::Duration::from_secs(20);
+
+/// How long we sleep when due tasks are held back by concurrency limits,
+/// in case the slot is freed by something other than a task finishing
+const HELD_BACK_POLL: std::time::Duration = std::time::Duration::from_secs(1);
 
 #[tracing::instrument(skip_all, fields(id = %task.id, endpoint = %task.endpoint))]
 async fn execute_task(
@@ -8,62 +91,520 @@ async fn execute_task(
     task: db::InflightTask,
     db: PgPool,
     events_tx: flume::Sender<Event>,
+    mut release_rx: watch::Receiver<bool>,
+    cancel_rx: oneshot::Receiver<()>,
+    options: Arc<Options>,
 ) {
-    let response = http
+    if task.cancel_requested {
+        tracing::info!("task was cancelled before it was invoked");
+        record_cancelled(&http, &options.endpoint_policy, task, &db, &events_tx).await;
+        return;
+    }
+
+    // the endpoint was allowed when the task was enqueued, but the policy
+    // or the endpoint's DNS records might have changed since. Retriable, so tasks
+    // queued before an upgrade run once their endpoints are allowed.
+    if let Err(reason) = check_endpoint(&options.endpoint_policy, &task.endpoint).await {
+        send_event(&events_tx, Event::TaskFailed).await;
+        tracing::error!("endpoint not allowed: {reason}");
+        task.failed(&db, &format!("endpoint not allowed: {reason}"), true, None)
+            .await;
+        return;
+    }
+
+    let response = tokio::select! {
+        response = invoke(&http, &task, &options) => response,
+        Ok(()) = cancel_rx => {
+            tracing::info!("invocation cancelled");
+            record_cancelled(&http, &options.endpoint_policy, task, &db, &events_tx).await;
+            return;
+        }
+        // we're shutting down and the drain timeout elapsed
+        _ = release_rx.wait_for(|release| *release).map(drop) => {
+            tracing::warn!("releasing unfinished task");
+            task.release(&db).await;
+            return;
+        }
+    };
+
+    let failure = match response {
+        Ok(InvokedTaskResponse::Success {}) => {
+            send_event(&events_tx, Event::TaskFinished).await;
+            tracing::info!("invocation completed");
+            task.done(&db).await;
+            return;
+        }
+        Ok(InvokedTaskResponse::Accepted { lease_seconds })
+            if lease_seconds > db::MAX_LEASE.as_secs() =>
+        {
+            InvocationError {
+                reason: format!(
+                    "can't accept a task for {lease_seconds}s, the longest lease is {}s",
+                    db::MAX_LEASE.as_secs()
+                ),
+                retriable: true,
+                retry_after: None,
+            }
+        }
+        Ok(InvokedTaskResponse::Accepted { lease_seconds }) => {
+            tracing::info!("invocation accepted for {lease_seconds}s");
+            task.accepted(&db, std::time::Duration::from_secs(lease_seconds))
+                .await;
+            return;
+        }
+        Ok(InvokedTaskResponse::Reschedule { when, data }) => match reschedule_at(&when) {
+            Some(_) if task.snooze_count as u32 >= options.max_snoozes => InvocationError {
+                reason: format!("rescheduled more than {} times", options.max_snoozes),
+                retriable: false,
+                retry_after: None,
+            },
+            Some(run_at) => {
+                send_event(&events_tx, Event::TaskRescheduled).await;
+                tracing::info!("invocation rescheduled to {run_at}");
+                task.rescheduled(&db, run_at, data.as_ref()).await;
+                return;
+            }
+            None => InvocationError {
+                reason: format!("can't reschedule to {when:?}"),
+                retriable: true,
+                retry_after: None,
+            },
+        },
+        Ok(InvokedTaskResponse::Failure { reason, retriable }) => InvocationError {
+            reason,
+            retriable,
+            retry_after: None,
+        },
+        Err(err) => err,
+    };
+
+    send_event(&events_tx, Event::TaskFailed).await;
+    tracing::error!("invocation failed: {}", failure.reason);
+    task.failed(&db, &failure.reason, failure.retriable, failure.retry_after)
+        .await;
+}
+
+/// When to run a rescheduled task, unless the delay is too far out to represent
+fn reschedule_at(when: &RescheduleAt) -> Option<chrono::DateTime<chrono::Utc>> {
+    match when {
+        RescheduleAt::At { run_at } => Some(*run_at),
+        RescheduleAt::After { delay_seconds } => {
+            let delay = std::time::Duration::from_secs(*delay_seconds);
+            let delay = chrono::Duration::from_std(delay).ok()?;
+            chrono::Utc::now().checked_add_signed(delay)
+        }
+    }
+}
+
+/// An invocation that failed without the endpoint responding with a failure
+struct InvocationError {
+    reason: String,
+    retriable: bool,
+    /// When the endpoint asked to be retried, with `Retry-After`
+    retry_after: Option<std::time::Duration>,
+}
+
+impl InvocationError {
+    fn from_reqwest(err: reqwest::Error, timeout: std::time::Duration) -> Self {
+        let reason = if err.is_timeout() {
+            format!("timed out after {}", humantime::format_duration(timeout))
+        } else {
+            err.to_string()
+        };
+        Self {
+            reason,
+            retriable: true,
+            retry_after: None,
+        }
+    }
+}
+
+async fn invoke(
+    http: &reqwest::Client,
+    task: &db::InflightTask,
+    options: &Options,
+) -> Result<InvokedTaskResponse, InvocationError> {
+    let timeout = options.timeout_for(task);
+    let body = serde_json::to_vec(&InvokedTaskPayload {
+        task_id: task.id,
+        name: &task.name,
+        attempt_id: &task.attempt_id,
+        attempt_token: &task.attempt_token,
+        job_name: &task.job_name[..],
+        input: &task.data,
+        retry_count: task.retry_count,
+        max_retries: task.max_retries,
+        created_at: &task.created_at,
+        scheduled_for: &task.scheduled_for,
+        started_at: &task.started_at,
+    })
+    .expect("serializing the invocation payload");
+
+    let mut headers = task_headers(task, &options.header_keys)?;
+    let credential = options.credentials.for_endpoint(&task.endpoint);
+    if credential.is_some() {
+        // the server's credentials take precedence over the task's
+        headers.remove(reqwest::header::AUTHORIZATION);
+    }
+    headers.insert(
+        reqwest::header::CONTENT_TYPE,
+        reqwest::header::HeaderValue::from_static("application/json"),
+    );
+    headers.insert(headers::TASK_ID, task.id.into());
+    headers.insert(headers::ATTEMPT_ID, header_value(&task.attempt_id));
+    headers.insert(
+        headers::SCHEDULED_FOR,
+        timestamp_header(&task.scheduled_for),
+    );
+    headers.insert(headers::STARTED_AT, timestamp_header(&task.started_at));
+    // names can be anything, but header values can't have control characters
+    if let Ok(name) = reqwest::header::HeaderValue::from_bytes(task.name.as_bytes()) {
+        headers.insert(headers::TASK_NAME, name);
+    }
+    let deadline = chrono::Duration::from_std(timeout)
+        .ok()
+        .and_then(|timeout| chrono::Utc::now().checked_add_signed(timeout));
+    if let Some(deadline) = deadline {
+        headers.insert(headers::DEADLINE, timestamp_header(&deadline));
+    }
+    if !options.signing_secrets.is_empty() {
+        // the same for every attempt, so endpoints can tell a retry from a new task
+        let webhook_id = task.id.to_string();
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = signing::signature(&options.signing_secrets, &webhook_id, timestamp, &body);
+        headers.insert(headers::WEBHOOK_ID, header_value(&webhook_id));
+        headers.insert(headers::WEBHOOK_TIMESTAMP, timestamp.into());
+        headers.insert(headers::WEBHOOK_SIGNATURE, header_value(&signature));
+    }
+
+    // covers reading the response body too
+    let mut request = http
         .post(&task.endpoint)
-        .json(&InvokedTaskPayload {
-            job_name: &task.job_name[..],
-            input: &task.data,
-            retry_count: task.retry_count,
-            max_retries: task.max_retries,
-            created_at: &task.created_at,
-        })
+        .headers(headers)
+        .timeout(timeout)
+        .body(body);
+    if let Some(credential) = credential {
+        request = credential.authenticate(request);
+    }
+    let response = request
         .send()
         .await
-        .and_then(|res| res.error_for_status());
+        .map_err(|err| InvocationError::from_reqwest(err, timeout))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = match status {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                retry_after(response.headers())
+            }
+            _ => None,
+        };
+        let excerpt = body_excerpt(response).await;
+        return Err(InvocationError {
+            reason: if excerpt.is_empty() {
+                format!("HTTP {status}")
+            } else {
+                format!("HTTP {status}: {excerpt}")
+            },
+            // the endpoint won't change its mind about a bad request, unless it timed out
+            // or is rate limited, or its job says it might
+            retriable: !status.is_client_error()
+                || matches!(
+                    status,
+                    StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+                )
+                || options.retriable_4xx_jobs.contains(&task.job_name),
+            retry_after,
+        });
+    }
 
-    let response = match response {
-        Err(err) => Err(err),
-        Ok(res) => res.json::<InvokedTaskResponse>().await,
+    // endpoints that don't know about pointguard respond with whatever they like
+    if options.response_mode_for(task) == db::ResponseMode::Lenient {
+        return Ok(InvokedTaskResponse::Success {});
     }
-    .unwrap_or_else(|err| InvokedTaskResponse::Failure {
-        reason: err.to_string(),
+
+    let body = response
+        .bytes()
+        .await
+        .map_err(|err| InvocationError::from_reqwest(err, timeout))?;
+    serde_json::from_slice(&body).map_err(|err| InvocationError {
+        reason: format!("invalid response: {err}: {}", excerpt(&body)),
         retriable: true,
-    });
-
-    match response {
-        InvokedTaskResponse::Success {} => {
-            events_tx
-                .send_async(Event::TaskFinished)
-                .await
-                .expect("send event");
-            tracing::info!("invocation completed");
-            task.done(&db).await;
+        retry_after: None,
+    })
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<std::time::Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => std::time::Duration::from_secs(seconds),
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                .to_std()
+                .unwrap_or_default()
         }
-        InvokedTaskResponse::Failure { reason, retriable } => {
-            events_tx
-                .send_async(Event::TaskFailed)
-                .await
-                .expect("send event");
-            tracing::error!("invocation failed: {reason}");
-            task.failed(&db, &reason, retriable).await;
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// The start of an error response, so failures say what went wrong
+async fn body_excerpt(mut response: reqwest::Response) -> String {
+    let mut body = Vec::new();
+    while body.len() <= MAX_ERROR_BODY {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) | Err(_) => break,
         }
+    }
+    excerpt(&body)
+}
+
+fn excerpt(body: &[u8]) -> String {
+    let truncated = body.len() > MAX_ERROR_BODY;
+    let body = String::from_utf8_lossy(&body[..body.len().min(MAX_ERROR_BODY)]);
+    let body = body.trim();
+    if truncated {
+        format!("{body}…")
+    } else {
+        body.to_string()
+    }
+}
+
+/// For values we generate, which are always valid
+fn header_value(value: &str) -> reqwest::header::HeaderValue {
+    value.parse().expect("valid header value")
+}
+
+fn timestamp_header(time: &chrono::DateTime<chrono::Utc>) -> reqwest::header::HeaderValue {
+    header_value(&time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+}
+
+async fn check_endpoint(policy: &EndpointPolicy, endpoint: &str) -> Result<(), String> {
+    let url = reqwest::Url::parse(endpoint).map_err(|err| format!("invalid endpoint: {err}"))?;
+    policy.check(&url).await
+}
+
+/// The headers the task was enqueued with. A task whose headers can't be decrypted
+/// fails for good, since retrying won't bring its key back.
+fn task_headers(
+    task: &db::InflightTask,
+    keys: &[db::HeaderKey],
+) -> Result<reqwest::header::HeaderMap, InvocationError> {
+    let task_headers =
+        db::decrypt_headers(keys, &task.headers).map_err(|reason| InvocationError {
+            reason,
+            retriable: false,
+            retry_after: None,
+        })?;
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in task_headers.iter() {
+        if headers::is_reserved(name) {
+            tracing::warn!("skipping reserved header {name:?}");
+            continue;
+        }
+        let header_name = reqwest::header::HeaderName::from_bytes(name.as_bytes());
+        let header_value = reqwest::header::HeaderValue::from_str(value);
+        match (header_name, header_value) {
+            (Ok(name), Ok(mut value)) => {
+                value.set_sensitive(true);
+                headers.insert(name, value);
+            }
+            _ => tracing::warn!("skipping invalid header {name:?}"),
+        }
+    }
+    Ok(headers)
+}
+
+/// Records the task as cancelled, and lets its cancel endpoint know
+async fn record_cancelled(
+    http: &reqwest::Client,
+    policy: &EndpointPolicy,
+    task: db::InflightTask,
+    db: &PgPool,
+    events_tx: &flume::Sender<Event>,
+) {
+    let cancel_endpoint = task.cancel_endpoint.clone();
+    let cancel_request = cancel_request(http, &task);
+    task.cancelled(db).await;
+    send_event(events_tx, Event::TaskCancelled).await;
+    let (Some(cancel_endpoint), Some(cancel_request)) = (cancel_endpoint, cancel_request) else {
+        return;
     };
+    // like the endpoint, it was allowed when the task was enqueued
+    if let Err(reason) = check_endpoint(policy, &cancel_endpoint).await {
+        tracing::warn!("cancel endpoint not allowed: {reason}");
+        return;
+    }
+    notify_cancelled(cancel_request).await;
+}
+
+/// The request to the task's cancel endpoint, if it has one
+fn cancel_request(
+    http: &reqwest::Client,
+    task: &db::InflightTask,
+) -> Option<reqwest::RequestBuilder> {
+    let cancel_endpoint = task.cancel_endpoint.as_ref()?;
+    let request = http
+        .post(cancel_endpoint)
+        .timeout(CANCEL_ENDPOINT_TIMEOUT)
+        .json(&CancelledTaskPayload {
+            task_id: task.id,
+            job_name: &task.job_name[..],
+            input: &task.data,
+            retry_count: task.retry_count,
+        });
+    Some(request)
+}
+
+/// Lets the cancel endpoint know so it can stop working on the task.
+/// This is best effort, the task is cancelled either way.
+async fn notify_cancelled(request: reqwest::RequestBuilder) {
+    let response = request.send().await.and_then(|res| res.error_for_status());
+
+    if let Err(err) = response {
+        tracing::warn!("can't notify the cancel endpoint: {err}");
+    }
+}
+
+/// Cancels running tasks when asked to. Requests for tasks
+/// we aren't running are for other workers.
+async fn listen_for_cancellations(db: PgPool, cancellations: Cancellations) {
+    loop {
+        let mut listener = match db::CancelTaskListener::new(&db).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("can't listen to cancel requests: {err}");
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        // catch up on requests sent while we weren't listening
+        match db::cancel_requested_tasks(&db).await {
+            Ok(ids) => ids.into_iter().for_each(|id| cancel(&cancellations, id)),
+            Err(err) => tracing::error!("can't fetch cancel requests: {err}"),
+        }
+
+        loop {
+            match listener.take().await {
+                Ok(Some(payload)) => cancel(&cancellations, payload.id),
+                Ok(None) => {
+                    tracing::warn!("lost the cancel requests listener connection");
+                    break;
+                }
+                Err(err) => {
+                    tracing::error!("cancel requests listener failed: {err}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn cancel(cancellations: &Cancellations, id: i64) {
+    if let Some(cancel_tx) = cancellations.lock().unwrap().remove(&id) {
+        _ = cancel_tx.send(());
+    }
+}
+
+/// Fails the accepted tasks that weren't reported on in time, so they are retried.
+/// Cancelling an accepted task expires its lease, so it's recorded as cancelled here.
+///
+/// Runs next to the claim loop, so leases expire even while every slot is busy,
+/// and every expired lease is handled on its own, so a slow cancel endpoint doesn't hold up the rest.
+async fn expire_leases(
+    http: reqwest::Client,
+    options: Arc<Options>,
+    db: PgPool,
+    events_tx: flume::Sender<Event>,
+    mut stop_rx: watch::Receiver<bool>,
+) {
+    let mut expiring = JoinSet::new();
+    let mut interval = tokio::time::interval(LEASE_CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop_rx.wait_for(|stop| *stop) => break,
+        }
+        // forget the ones that are done
+        while let Some(Some(_)) = expiring.join_next().now_or_never() {}
+
+        let tasks = db::expired_leases(&db).await.unwrap_or_else(|err| {
+            tracing::error!("Can't fetch expired leases: {err}");
+            vec![]
+        });
+        for task in tasks {
+            expiring.spawn(expire_lease(
+                http.clone(),
+                options.clone(),
+                db.clone(),
+                events_tx.clone(),
+                task,
+            ));
+        }
+    }
+
+    // the tasks are ours now, so finish recording them before we stop
+    while expiring.join_next().await.is_some() {}
+}
+
+async fn expire_lease(
+    http: reqwest::Client,
+    options: Arc<Options>,
+    db: PgPool,
+    events_tx: flume::Sender<Event>,
+    task: db::InflightTask,
+) {
+    if task.cancel_requested {
+        tracing::info!(id = %task.id, "accepted task cancelled");
+        record_cancelled(&http, &options.endpoint_policy, task, &db, &events_tx).await;
+        return;
+    }
+    tracing::warn!(id = %task.id, "lease expired");
+    send_event(&events_tx, Event::TaskFailed).await;
+    task.failed(&db, "lease expired", true, None).await;
+}
+
+/// Events are best effort, and no one is listening once the server has shut down
+async fn send_event(events_tx: &flume::Sender<Event>, event: Event) {
+    _ = events_tx.send_async(event).await;
 }
 
 pub async fn run(
     db: db::postgres::PgPool,
     termination: impl Future<Output = ()>,
     events_tx: flume::Sender<Event>,
+    listener_health: db::ListenerHealth,
+    options: Options,
 ) {
     tokio::pin!(termination);
-    let mut listener = db::TaskListener::new(&db)
-        .await
-        .expect("listen to task queue");
+    let mut listener = db::TaskListener::new(&db, listener_health);
+    listener.connect().await;
 
-    let http = reqwest::Client::new();
+    let http = http_client::new(options.endpoint_policy.clone());
+    let options = Arc::new(options);
+    let semaphore = Arc::new(Semaphore::new(options.concurrency));
+    let (release_tx, release_rx) = watch::channel(false);
+    let cancellations = Cancellations::default();
+    let cancel_listener = tokio::spawn(listen_for_cancellations(db.clone(), cancellations.clone()));
+    let (stop_expiring_tx, stop_expiring_rx) = watch::channel(false);
+    let lease_expiry = tokio::spawn(expire_leases(
+        http.clone(),
+        options.clone(),
+        db.clone(),
+        events_tx.clone(),
+        stop_expiring_rx,
+    ));
 
-    loop {
+    'claiming: loop {
         tokio::select! {
             _ = &mut termination => {
                 tracing::info!("shutting down");
@@ -73,36 +614,154 @@ pub async fn run(
             }
         };
 
-        let mut tasks = db::free_tasks(&db, 5).await.unwrap_or_else(|err| {
-            tracing::error!("Can't fetch tasks: {err}");
-            vec![]
-        });
+        // only claim tasks we have room to run, so a burst waits in the queue
+        // instead of piling up as in-flight requests
+        let permit = tokio::select! {
+            permit = semaphore.clone().acquire_owned() => permit.expect("semaphore is never closed"),
+            _ = &mut termination => {
+                tracing::info!("shutting down");
+                break;
+            }
+        };
+        let mut permits = vec![permit];
+        while permits.len() < options.batch_size {
+            match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => permits.push(permit),
+                Err(_) => break,
+            }
+        }
+
+        let mut tasks = db::free_tasks(&db, permits.len() as i64)
+            .await
+            .unwrap_or_else(|err| {
+                tracing::error!("Can't fetch tasks: {err}");
+                vec![]
+            });
 
         if tasks.is_empty() {
-            tokio::select! {
-                _ = tokio::time::sleep(std::time::Duration::from_secs(20)) => {},
-                _ = listener.take() => {
-                    tracing::info!("woke up from listener");
-                },
-                _ = &mut termination => {
-                    tracing::info!("shutting down");
-                    break;
+            drop(permits);
+
+            let next_run_at = db::next_run_at(&db).await.unwrap_or_else(|err| {
+                tracing::error!("Can't fetch the next task: {err}");
+                None
+            });
+            // tasks that are due but weren't claimed are held back by a concurrency limit,
+            // and get a chance whenever a task finishes
+            let held_back = next_run_at.is_some_and(|run_at| run_at <= chrono::Utc::now());
+            let mut wake_at = tokio::time::Instant::now() + MAX_IDLE;
+            if held_back {
+                wake_at = tokio::time::Instant::now() + HELD_BACK_POLL;
+            } else if let Some(next_run_at) = next_run_at {
+                wake_at = wake_at.min(instant_at(next_run_at));
+            }
+
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(wake_at) => break,
+                    wakeup = listener.take() => match wakeup {
+                        // a delayed task, keep sleeping unless it's due sooner
+                        db::TaskWakeup::Notified(payload) if payload.run_at > chrono::Utc::now() => {
+                            wake_at = wake_at.min(instant_at(payload.run_at));
+                        }
+                        db::TaskWakeup::Finished if held_back => break,
+                        db::TaskWakeup::Finished => {}
+                        db::TaskWakeup::Notified(_) => {
+                            tracing::info!("woke up from listener");
+                            break;
+                        }
+                        db::TaskWakeup::Connected => {
+                            tracing::info!("listener connected, looking for missed tasks");
+                            break;
+                        }
+                    },
+                    _ = &mut termination => {
+                        tracing::info!("shutting down");
+                        break 'claiming;
+                    }
                 }
             }
             continue;
         }
 
-        for task in tasks.drain(..) {
-            events_tx
-                .send_async(Event::TaskInvoked)
-                .await
-                .expect("send event");
-            tokio::spawn(execute_task(
-                http.clone(),
-                task,
-                db.clone(),
-                events_tx.clone(),
-            ));
+        // permits we didn't get tasks for are released when `permits` is dropped
+        for (task, permit) in tasks.drain(..).zip(permits) {
+            send_event(&events_tx, Event::TaskInvoked).await;
+            let http = http.clone();
+            let db = db.clone();
+            let events_tx = events_tx.clone();
+            let semaphore = semaphore.clone();
+            let release_rx = release_rx.clone();
+            let cancellations = cancellations.clone();
+            let options = options.clone();
+            let (cancel_tx, cancel_rx) = oneshot::channel();
+            cancellations.lock().unwrap().insert(task.id, cancel_tx);
+            tokio::spawn(async move {
+                let id = task.id;
+                execute_task(
+                    http,
+                    task,
+                    db,
+                    events_tx.clone(),
+                    release_rx,
+                    cancel_rx,
+                    options.clone(),
+                )
+                .await;
+                cancellations.lock().unwrap().remove(&id);
+                drop(permit);
+                send_event(&events_tx, utilization(&semaphore, options.concurrency)).await;
+            });
         }
+
+        // a cancel request sent after we claimed the tasks, but before they could
+        // be cancelled above, was ignored by the cancellations listener
+        match db::cancel_requested_tasks(&db).await {
+            Ok(ids) => ids.into_iter().for_each(|id| cancel(&cancellations, id)),
+            Err(err) => tracing::error!("can't fetch cancel requests: {err}"),
+        }
+
+        send_event(&events_tx, utilization(&semaphore, options.concurrency)).await;
     }
+
+    drain(&semaphore, &options, release_tx).await;
+    cancel_listener.abort();
+    _ = stop_expiring_tx.send(true);
+    _ = lease_expiry.await;
+}
+
+/// Waits for the running tasks to finish, releasing the ones
+/// that are still running after the drain timeout back to the queue
+async fn drain(semaphore: &Semaphore, options: &Options, release_tx: watch::Sender<bool>) {
+    let running = options.concurrency - semaphore.available_permits();
+    if running == 0 {
+        return;
+    }
+
+    tracing::info!(
+        "waiting up to {:?} for {running} running tasks to finish",
+        options.drain_timeout
+    );
+    // every permit is back once every task is done
+    let all_permits = options.concurrency as u32;
+    let drained = tokio::time::timeout(options.drain_timeout, semaphore.acquire_many(all_permits));
+    if drained.await.is_err() {
+        tracing::warn!(
+            "releasing {} unfinished tasks",
+            options.concurrency - semaphore.available_permits()
+        );
+        _ = release_tx.send(true);
+        _ = semaphore.acquire_many(all_permits).await;
+    }
+}
+
+fn utilization(semaphore: &Semaphore, concurrency: usize) -> Event {
+    Event::WorkerUtilization {
+        running: concurrency - semaphore.available_permits(),
+        concurrency,
+    }
+}
+
+fn instant_at(time: chrono::DateTime<chrono::Utc>) -> tokio::time::Instant {
+    let until = (time - chrono::Utc::now()).to_std().unwrap_or_default();
+    tokio::time::Instant::now() + until
 }
@@ -0,0 +1,77 @@
+use crate::database::{self, DatabaseArgs};
+use std::{collections::HashSet, path::Path};
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TenantsFile {
+    tenants: Vec<TenantConfig>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TenantConfig {
+    /// The tenant name, used in the `/t/:name` URL prefix
+    pub name: String,
+    /// The database schema that holds the tenant's tasks
+    pub schema: String,
+    /// A PostgreSQL connection string. Defaults to the server's `--database-url`.
+    pub database_url: Option<String>,
+    /// A read replica for the tenant's listing endpoints.
+    /// Defaults to the server's `--read-database-url` when the tenant uses the server's database.
+    pub read_database_url: Option<String>,
+}
+
+impl TenantConfig {
+    pub fn database_args(&self, defaults: &DatabaseArgs) -> DatabaseArgs {
+        let mut database = defaults.clone();
+        if let Some(database_url) = &self.database_url {
+            database.database_url = database_url.clone();
+        }
+        database.schema = Some(self.schema.clone());
+        database
+    }
+
+    pub fn read_database_url<'a>(&'a self, default: Option<&'a str>) -> Option<&'a str> {
+        match (&self.read_database_url, &self.database_url) {
+            (Some(url), _) => Some(url),
+            (None, None) => default,
+            (None, Some(_)) => None,
+        }
+    }
+}
+
+/// Reads a tenants file, which looks like:
+///
+/// ```json
+/// { "tenants": [{ "name": "acme", "schema": "acme" }] }
+/// ```
+pub fn load(path: &Path) -> Vec<TenantConfig> {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("reading tenants file {}: {err}", path.display()));
+    let file: TenantsFile = serde_json::from_str(&contents)
+        .unwrap_or_else(|err| panic!("parsing tenants file {}: {err}", path.display()));
+
+    let mut names = HashSet::new();
+    for tenant in &file.tenants {
+        let valid_name = !tenant.name.is_empty()
+            && tenant
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        assert!(
+            valid_name,
+            "tenant name {:?} can only contain letters, digits, '-' and '_'",
+            tenant.name
+        );
+        if let Err(err) = database::parse_schema(&tenant.schema) {
+            panic!("tenant {:?}: {err}", tenant.name);
+        }
+        assert!(
+            names.insert(&tenant.name),
+            "tenant {:?} is defined more than once",
+            tenant.name
+        );
+    }
+
+    file.tenants
+}
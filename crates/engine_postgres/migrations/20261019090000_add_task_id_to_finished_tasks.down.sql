@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS finished_tasks_task_id_idx;
+ALTER TABLE finished_tasks DROP COLUMN task_id;
@@ -0,0 +1,5 @@
+ALTER TABLE finished_tasks ADD COLUMN task_id BIGINT;
+
+comment on column finished_tasks.task_id is 'the ID the task had in the tasks table';
+
+CREATE INDEX finished_tasks_task_id_idx ON finished_tasks (task_id);
@@ -0,0 +1 @@
+DROP FUNCTION IF EXISTS pointguard_channel(text);
@@ -0,0 +1,7 @@
+-- LISTEN/NOTIFY channels are global to the database, so we namespace them
+-- by the schema to keep deployments that share a database apart.
+CREATE OR REPLACE FUNCTION pointguard_channel(name text) RETURNS text AS $$
+  SELECT 'pointguard:' || current_schema() || ':' || name
+$$ LANGUAGE sql STABLE;
+
+comment on function pointguard_channel(text) is 'the notification channel for the given queue in the current schema';
@@ -0,0 +1,18 @@
+DO $$
+DECLARE
+  id_seq text := pg_get_serial_sequence('finished_tasks', 'id');
+BEGIN
+  CREATE TABLE finished_tasks_unpartitioned (
+    LIKE finished_tasks INCLUDING DEFAULTS INCLUDING COMMENTS
+  );
+  INSERT INTO finished_tasks_unpartitioned SELECT * FROM finished_tasks;
+
+  -- dropping the partitioned table would drop the sequence it owns
+  EXECUTE format('ALTER SEQUENCE %s OWNED BY finished_tasks_unpartitioned.id', id_seq);
+  DROP TABLE finished_tasks;
+
+  ALTER TABLE finished_tasks_unpartitioned RENAME TO finished_tasks;
+  ALTER TABLE finished_tasks ADD CONSTRAINT failed_tasks_pkey PRIMARY KEY (id);
+  CREATE INDEX finished_tasks_task_id_idx ON finished_tasks (task_id);
+END
+$$;
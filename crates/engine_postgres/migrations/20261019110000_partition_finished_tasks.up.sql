@@ -0,0 +1,49 @@
+-- Partition finished_tasks by day (UTC) on created_at, so expired rows can be
+-- dropped a partition at a time instead of being deleted row by row.
+--
+-- The existing table is not copied: it becomes the partition for everything
+-- created before tomorrow, and is dropped as a whole once all of it expired.
+DO $$
+DECLARE
+  id_seq text := pg_get_serial_sequence('finished_tasks', 'id');
+  boundary timestamptz := date_trunc('day', now(), 'UTC') + interval '1 day';
+  legacy_pkey text;
+  day timestamptz;
+BEGIN
+  ALTER TABLE finished_tasks RENAME TO finished_tasks_legacy;
+  ALTER INDEX finished_tasks_task_id_idx RENAME TO finished_tasks_legacy_task_id_idx;
+
+  -- a partitioned table's primary key has to include the partition key
+  SELECT conname INTO legacy_pkey
+  FROM pg_constraint
+  WHERE conrelid = 'finished_tasks_legacy'::regclass AND contype = 'p';
+  EXECUTE format('ALTER TABLE finished_tasks_legacy DROP CONSTRAINT %I', legacy_pkey);
+
+  CREATE TABLE finished_tasks (
+    LIKE finished_tasks_legacy INCLUDING DEFAULTS INCLUDING COMMENTS,
+    PRIMARY KEY (id, created_at)
+  ) PARTITION BY RANGE (created_at);
+
+  EXECUTE format('ALTER SEQUENCE %s OWNED BY finished_tasks.id', id_seq);
+
+  CREATE INDEX finished_tasks_task_id_idx ON finished_tasks (task_id);
+  CREATE INDEX finished_tasks_created_at_idx ON finished_tasks (created_at);
+
+  EXECUTE format(
+    'ALTER TABLE finished_tasks ATTACH PARTITION finished_tasks_legacy FOR VALUES FROM (MINVALUE) TO (%L)',
+    boundary
+  );
+
+  -- the maintenance loop keeps creating these ahead of time, but we don't
+  -- want to depend on it running before the first task finishes tomorrow.
+  FOR i IN 0..6 LOOP
+    day := boundary + i * interval '1 day';
+    EXECUTE format(
+      'CREATE TABLE IF NOT EXISTS %I PARTITION OF finished_tasks FOR VALUES FROM (%L) TO (%L)',
+      'finished_tasks_p' || to_char(day AT TIME ZONE 'UTC', 'YYYYMMDD'),
+      day,
+      day + interval '1 day'
+    );
+  END LOOP;
+END
+$$;
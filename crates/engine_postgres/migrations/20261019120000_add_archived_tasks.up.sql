@@ -0,0 +1,11 @@
+-- finished tasks loaded back from archive files, for investigation.
+-- Not partitioned, since archives can be of any age.
+CREATE TABLE archived_tasks (
+  LIKE finished_tasks INCLUDING COMMENTS,
+  PRIMARY KEY (id)
+);
+
+comment on table archived_tasks is 'finished tasks imported from archive files';
+
+CREATE INDEX archived_tasks_task_id_idx ON archived_tasks (task_id);
+CREATE INDEX archived_tasks_created_at_idx ON archived_tasks (created_at);
@@ -0,0 +1,2 @@
+DROP TABLE concurrency_limits;
+ALTER TABLE tasks DROP COLUMN endpoint_host;
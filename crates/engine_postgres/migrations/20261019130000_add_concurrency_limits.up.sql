@@ -0,0 +1,23 @@
+-- the host part of the endpoint URL, so tasks can be limited per host
+ALTER TABLE tasks ADD COLUMN endpoint_host text
+  GENERATED ALWAYS AS (lower(substring(endpoint from '^[^:/]+://(?:[^@/]*@)?(\[[^]]*\]|[^/:?#]*)'))) STORED;
+
+comment on column tasks.endpoint_host is 'the host of the endpoint, for concurrency limits';
+
+CREATE TABLE concurrency_limits (
+  scope text not null check (scope in ('job_name', 'endpoint_host')),
+  key text not null,
+  max_concurrency integer not null check (max_concurrency > 0),
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now(),
+
+  primary key (scope, key)
+);
+
+comment on table concurrency_limits is 'how many tasks can run at once across all workers';
+comment on column concurrency_limits.scope is 'what the key is matched against: job_name or endpoint_host';
+comment on column concurrency_limits.key is 'the job name or endpoint host to limit';
+comment on column concurrency_limits.max_concurrency is 'how many matching tasks can be running at once';
+
+CREATE INDEX tasks_job_name_idx ON tasks (job_name);
+CREATE INDEX tasks_endpoint_host_idx ON tasks (endpoint_host);
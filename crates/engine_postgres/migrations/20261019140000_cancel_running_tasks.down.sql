@@ -0,0 +1,4 @@
+ALTER TABLE archived_tasks DROP COLUMN cancelled;
+ALTER TABLE finished_tasks DROP COLUMN cancelled;
+ALTER TABLE tasks DROP COLUMN cancel_endpoint;
+ALTER TABLE tasks DROP COLUMN cancel_requested_at;
@@ -0,0 +1,11 @@
+ALTER TABLE tasks ADD COLUMN cancel_requested_at timestamptz;
+ALTER TABLE tasks ADD COLUMN cancel_endpoint varchar(1024);
+
+comment on column tasks.cancel_requested_at is 'when cancelling the task was requested while it was running';
+comment on column tasks.cancel_endpoint is 'an endpoint to notify when the task is cancelled while running';
+
+ALTER TABLE finished_tasks ADD COLUMN cancelled boolean not null default false;
+ALTER TABLE archived_tasks ADD COLUMN cancelled boolean not null default false;
+
+comment on column finished_tasks.cancelled is 'whether the task was cancelled while running';
+comment on column archived_tasks.cancelled is 'whether the task was cancelled while running';
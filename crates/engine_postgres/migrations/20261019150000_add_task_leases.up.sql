@@ -0,0 +1,9 @@
+ALTER TABLE tasks ADD COLUMN attempt_token_hash text;
+ALTER TABLE tasks ADD COLUMN lease_expires_at timestamptz;
+ALTER TABLE tasks ADD COLUMN lease_duration interval;
+
+comment on column tasks.attempt_token_hash is 'sha256 of the token the endpoint uses to report on the current attempt';
+comment on column tasks.lease_expires_at is 'when an accepted task is reclaimed unless the endpoint completes it or sends a heartbeat';
+comment on column tasks.lease_duration is 'how long a heartbeat extends the lease by';
+
+CREATE INDEX tasks_lease_expires_at_idx ON tasks (lease_expires_at) WHERE lease_expires_at IS NOT NULL;
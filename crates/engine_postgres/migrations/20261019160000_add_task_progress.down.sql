@@ -0,0 +1,3 @@
+ALTER TABLE tasks DROP COLUMN progress_updated_at;
+ALTER TABLE tasks DROP COLUMN progress_message;
+ALTER TABLE tasks DROP COLUMN progress_percent;
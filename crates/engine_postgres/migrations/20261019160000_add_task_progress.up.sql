@@ -0,0 +1,7 @@
+ALTER TABLE tasks ADD COLUMN progress_percent real check (progress_percent between 0 and 100);
+ALTER TABLE tasks ADD COLUMN progress_message text;
+ALTER TABLE tasks ADD COLUMN progress_updated_at timestamptz;
+
+comment on column tasks.progress_percent is 'how far along the current attempt is, as reported by the endpoint';
+comment on column tasks.progress_message is 'what the current attempt is doing, as reported by the endpoint';
+comment on column tasks.progress_updated_at is 'when the endpoint last reported progress';
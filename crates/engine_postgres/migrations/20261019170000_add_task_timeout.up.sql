@@ -0,0 +1,3 @@
+ALTER TABLE tasks ADD COLUMN timeout interval check (timeout > interval '0');
+
+comment on column tasks.timeout is 'how long to wait for the endpoint to respond, overriding the job and server defaults';
@@ -0,0 +1,3 @@
+ALTER TABLE tasks ADD COLUMN headers jsonb not null default '{}';
+
+comment on column tasks.headers is 'headers sent with every invocation, only their names are returned by the api';
@@ -0,0 +1,3 @@
+ALTER TABLE tasks ADD COLUMN response_mode text check (response_mode in ('strict', 'lenient'));
+
+comment on column tasks.response_mode is 'how the endpoint response is read, overriding the job default';
@@ -0,0 +1,3 @@
+ALTER TABLE tasks ADD COLUMN attempt_id text;
+
+comment on column tasks.attempt_id is 'identifies the current attempt, sent to the endpoint with the invocation';
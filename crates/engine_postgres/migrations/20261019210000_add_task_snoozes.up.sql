@@ -0,0 +1,3 @@
+ALTER TABLE tasks ADD COLUMN snooze_count integer not null default 0;
+
+comment on column tasks.snooze_count is 'how many times the endpoint rescheduled the task, which does not count as a retry';
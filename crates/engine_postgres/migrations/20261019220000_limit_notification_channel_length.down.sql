@@ -0,0 +1,3 @@
+CREATE OR REPLACE FUNCTION pointguard_channel(name text) RETURNS text AS $$
+  SELECT 'pointguard:' || current_schema() || ':' || name
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,10 @@
+-- Channel names are limited to 63 bytes: longer ones make pg_notify fail and
+-- are truncated by LISTEN. Schemas that would go over are replaced by their hash,
+-- which keeps the channels of different schemas apart.
+CREATE OR REPLACE FUNCTION pointguard_channel(name text) RETURNS text AS $$
+  SELECT CASE
+    WHEN octet_length(channel) <= 63 THEN channel
+    ELSE 'pointguard:' || md5(current_schema()) || ':' || name
+  END
+  FROM (SELECT 'pointguard:' || current_schema() || ':' || name AS channel) channels
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,4 @@
+-- fails if there is no partition for some of the rows, rather than losing them
+ALTER TABLE finished_tasks DETACH PARTITION finished_tasks_default;
+INSERT INTO finished_tasks SELECT * FROM finished_tasks_default;
+DROP TABLE finished_tasks_default;
@@ -0,0 +1,4 @@
+-- Catches finished tasks no daily partition covers, like when maintenance stalls
+-- past the partitions it created ahead of time, so finishing a task never fails.
+-- Maintenance moves them out when it creates the partition they belong to.
+CREATE TABLE finished_tasks_default PARTITION OF finished_tasks DEFAULT;
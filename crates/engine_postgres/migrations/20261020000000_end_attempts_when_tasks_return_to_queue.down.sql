@@ -0,0 +1,2 @@
+DROP TRIGGER tasks_end_attempt ON tasks;
+DROP FUNCTION pointguard_end_attempt();
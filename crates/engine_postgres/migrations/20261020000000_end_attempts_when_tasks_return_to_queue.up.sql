@@ -0,0 +1,22 @@
+-- Whenever a task goes back to the queue, like when it is released, rescheduled or retried,
+-- everything about the attempt that was running it is cleared here, so new attempt
+-- columns only need to be added in one place.
+CREATE FUNCTION pointguard_end_attempt() RETURNS trigger AS $$
+BEGIN
+  NEW.started_at := NULL;
+  NEW.attempt_token_hash := NULL;
+  NEW.attempt_id := NULL;
+  NEW.lease_expires_at := NULL;
+  NEW.lease_duration := NULL;
+  NEW.progress_percent := NULL;
+  NEW.progress_message := NULL;
+  NEW.progress_updated_at := NULL;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tasks_end_attempt
+  BEFORE UPDATE OF worker_id ON tasks
+  FOR EACH ROW
+  WHEN (OLD.worker_id IS NOT NULL AND NEW.worker_id IS NULL)
+  EXECUTE FUNCTION pointguard_end_attempt();
@@ -0,0 +1,106 @@
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// A full `finished_tasks` row, as it is written to and read from archive files
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedTask {
+    pub id: i64,
+    pub task_id: Option<i64>,
+    pub job_name: String,
+    pub name: String,
+    pub endpoint: String,
+    pub data: serde_json::Value,
+    pub error_message: Option<String>,
+    pub retries: i32,
+    pub worker_id: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub task_created_at: chrono::DateTime<chrono::Utc>,
+    /// Missing in archives written before tasks could be cancelled
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+/// Deletes up to `limit` finished tasks created before `before` and returns them.
+///
+/// The rows are only gone once `tx` commits, so write them somewhere safe first.
+pub async fn take_finished_tasks_to_archive(
+    tx: &mut Transaction<'_, Postgres>,
+    before: chrono::DateTime<chrono::Utc>,
+    limit: i64,
+) -> Result<Vec<ArchivedTask>, sqlx::Error> {
+    sqlx::query_as!(
+        ArchivedTask,
+        "
+        DELETE FROM finished_tasks
+        WHERE (id, created_at) IN (
+            SELECT id, created_at
+            FROM finished_tasks
+            WHERE created_at < $1
+            ORDER BY created_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING
+            id,
+            task_id,
+            job_name,
+            name,
+            endpoint,
+            data,
+            error_message,
+            retries,
+            worker_id,
+            created_at,
+            started_at,
+            task_created_at,
+            cancelled
+        ",
+        before,
+        limit,
+    )
+    .fetch_all(&mut **tx)
+    .await
+}
+
+/// Loads archived tasks into `archived_tasks`. Tasks that were already imported are skipped.
+///
+/// Returns how many tasks were inserted.
+pub async fn import_archived_tasks(
+    db: &PgPool,
+    tasks: &[ArchivedTask],
+) -> Result<u64, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let mut inserted = 0;
+
+    for task in tasks {
+        inserted += sqlx::query!(
+            "
+            INSERT INTO archived_tasks
+            (id, task_id, job_name, name, endpoint, data, error_message, retries, worker_id, created_at, started_at, task_created_at, cancelled)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            ON CONFLICT (id) DO NOTHING
+            ",
+            task.id,
+            task.task_id,
+            task.job_name,
+            task.name,
+            task.endpoint,
+            task.data,
+            task.error_message,
+            task.retries,
+            task.worker_id,
+            task.created_at,
+            task.started_at,
+            task.task_created_at,
+            task.cancelled,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    }
+
+    tx.commit().await?;
+
+    Ok(inserted)
+}
@@ -0,0 +1,135 @@
+use sqlx::PgPool;
+
+/// What a [`ConcurrencyLimit`] key is matched against
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum ConcurrencyLimitScope {
+    /// Tasks with the same job name
+    JobName,
+    /// Tasks whose endpoint is on the same host, like `api.example.com`
+    EndpointHost,
+}
+
+impl ConcurrencyLimitScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::JobName => "job_name",
+            Self::EndpointHost => "endpoint_host",
+        }
+    }
+
+    fn from_db(scope: &str) -> Result<Self, sqlx::Error> {
+        match scope {
+            "job_name" => Ok(Self::JobName),
+            "endpoint_host" => Ok(Self::EndpointHost),
+            _ => Err(sqlx::Error::Decode(
+                format!("unknown concurrency limit scope {scope:?}").into(),
+            )),
+        }
+    }
+}
+
+/// How many matching tasks can be running at once, across all workers
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcurrencyLimit {
+    pub scope: ConcurrencyLimitScope,
+    pub key: String,
+    pub max_concurrency: i32,
+    /// How many matching tasks are running, or accepted by their endpoint, right now
+    pub running: i64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub async fn concurrency_limits(db: &PgPool) -> Result<Vec<ConcurrencyLimit>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "
+        SELECT
+            scope,
+            key,
+            max_concurrency,
+            (
+                SELECT count(*)
+                FROM tasks
+                LEFT JOIN running_workers ON tasks.worker_id = running_workers.application_name
+                WHERE (running_workers.application_name IS NOT NULL OR tasks.lease_expires_at IS NOT NULL)
+                  AND CASE concurrency_limits.scope
+                    WHEN 'job_name' THEN tasks.job_name = concurrency_limits.key
+                    ELSE tasks.endpoint_host = concurrency_limits.key
+                  END
+            ) AS \"running!\",
+            created_at,
+            updated_at
+        FROM concurrency_limits
+        ORDER BY scope, key
+        "
+    )
+    .fetch_all(db)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(ConcurrencyLimit {
+                scope: ConcurrencyLimitScope::from_db(&row.scope)?,
+                key: row.key,
+                max_concurrency: row.max_concurrency,
+                running: row.running,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            })
+        })
+        .collect()
+}
+
+/// Creates or updates a limit. Workers pick it up on their next claim,
+/// and don't hold it up while they are claiming.
+pub async fn set_concurrency_limit(
+    db: &PgPool,
+    scope: ConcurrencyLimitScope,
+    key: &str,
+    max_concurrency: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "
+        INSERT INTO concurrency_limits (scope, key, max_concurrency)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (scope, key) DO UPDATE
+        SET
+            max_concurrency = excluded.max_concurrency,
+            updated_at = now()
+        ",
+        scope.as_str(),
+        normalize_key(scope, key),
+        max_concurrency,
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Returns whether there was a limit to remove
+pub async fn remove_concurrency_limit(
+    db: &PgPool,
+    scope: ConcurrencyLimitScope,
+    key: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM concurrency_limits WHERE scope = $1 AND key = $2",
+        scope.as_str(),
+        normalize_key(scope, key),
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Hosts are case insensitive, and `tasks.endpoint_host` is stored in lower case
+fn normalize_key(scope: ConcurrencyLimitScope, key: &str) -> String {
+    match scope {
+        ConcurrencyLimitScope::JobName => key.to_string(),
+        ConcurrencyLimitScope::EndpointHost => key.to_lowercase(),
+    }
+}
@@ -1 +1,17 @@
-pub const NEW_TASK_QUEUE: &str = "pointguard:new_task";
+//! Notification queue names and advisory lock keys.
+//!
+//! The channel we actually LISTEN/NOTIFY on is derived from the queue names
+//! by the `pointguard_channel` SQL function, which namespaces it by schema.
+//! Advisory locks are taken with two keys: one of the lock keys here, and a hash
+//! that namespaces it, like the hash of the schema.
+
+pub const NEW_TASK_QUEUE: &str = "new_task";
+pub const FINISHED_TASK_QUEUE: &str = "finished_task";
+pub const CANCEL_TASK_QUEUE: &str = "cancel_task";
+
+/// Held while the `finished_tasks` partitions are maintained. "PG" in the high bytes
+/// makes our locks easy to tell apart in `pg_locks`.
+pub const PARTITION_MAINTENANCE_LOCK: i32 = 0x5047_0001;
+
+/// Held while claiming tasks under a concurrency limit, with a hash of the limit
+pub const CONCURRENCY_LIMIT_LOCK: i32 = 0x5047_0002;
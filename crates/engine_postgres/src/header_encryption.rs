@@ -0,0 +1,117 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, OsRng, Payload},
+    Aes256Gcm, KeyInit, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{collections::HashMap, str::FromStr};
+
+/// Encrypted header values look like `enc:v1:<base64 nonce and ciphertext>`
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_LENGTH: usize = 12;
+
+/// Encrypts the headers tasks are enqueued with, so credentials aren't stored in plaintext
+#[derive(Clone)]
+pub struct HeaderKey(Aes256Gcm);
+
+/// Keeps the key out of the logs
+impl std::fmt::Debug for HeaderKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HeaderKey(..)")
+    }
+}
+
+impl FromStr for HeaderKey {
+    type Err = String;
+
+    /// Keys are 32 random bytes, base64 encoded, like `openssl rand -base64 32` prints
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let key = STANDARD
+            .decode(key)
+            .map_err(|err| format!("header encryption key is not valid base64: {err}"))?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| "header encryption keys are 32 bytes".to_string())?;
+        Ok(Self(cipher))
+    }
+}
+
+impl HeaderKey {
+    /// The header name is authenticated too, so a value can't be moved to another header
+    fn encrypt(&self, name: &str, value: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = name.to_ascii_lowercase();
+        let ciphertext = self
+            .0
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .expect("encrypting a header value");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        format!("{ENCRYPTED_PREFIX}{}", STANDARD.encode(sealed))
+    }
+
+    fn decrypt(&self, name: &str, sealed: &[u8]) -> Option<String> {
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        let aad = name.to_ascii_lowercase();
+        let value = self
+            .0
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .ok()?;
+        String::from_utf8(value).ok()
+    }
+}
+
+/// Encrypts every header value with the first key, which is the newest one
+pub fn encrypt_headers(
+    keys: &[HeaderKey],
+    headers: HashMap<String, String>,
+) -> HashMap<String, String> {
+    if headers.is_empty() {
+        return headers;
+    }
+    let key = keys.first().expect("a header encryption key");
+    headers
+        .into_iter()
+        .map(|(name, value)| {
+            let value = key.encrypt(&name, &value);
+            (name, value)
+        })
+        .collect()
+}
+
+/// Decrypts header values with whichever key encrypted them, so keys can be rotated
+/// by adding the new one first and removing the old one once no task uses it.
+/// Values stored before headers were encrypted are returned as they are.
+pub fn decrypt_headers(
+    keys: &[HeaderKey],
+    headers: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let Some(encoded) = value.strip_prefix(ENCRYPTED_PREFIX) else {
+                return Ok((name.clone(), value.clone()));
+            };
+            let sealed = STANDARD
+                .decode(encoded)
+                .ok()
+                .filter(|sealed| sealed.len() > NONCE_LENGTH)
+                .ok_or_else(|| format!("header {name:?} is not a valid encrypted value"))?;
+            let value = keys
+                .iter()
+                .find_map(|key| key.decrypt(name, &sealed))
+                .ok_or_else(|| format!("no header encryption key decrypts header {name:?}"))?;
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
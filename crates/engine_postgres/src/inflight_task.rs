@@ -1,4 +1,5 @@
-use std::fmt::Display;
+use crate::constants;
+use std::{collections::HashMap, fmt::Display};
 
 #[derive(Debug)]
 pub struct InflightTask {
@@ -11,10 +12,68 @@ pub struct InflightTask {
 
     pub max_retries: i32,
     pub retry_count: i32,
+    /// How many times the endpoint rescheduled the task, see [`InflightTask::rescheduled`]
+    pub snooze_count: i32,
 
+    /// Notified when the task is cancelled while running
+    pub cancel_endpoint: Option<String>,
+    /// Whether cancelling the task was requested before we took it, like when its worker
+    /// was asked to cancel it but shut down or crashed first. It should be recorded
+    /// as cancelled instead of being run.
+    pub cancel_requested: bool,
+
+    /// Lets the endpoint report on this attempt, see [`InflightTask::accepted`]
+    pub attempt_token: String,
+    /// Identifies this attempt, for idempotency and logs
+    pub attempt_id: String,
+    /// When the task was due
+    pub scheduled_for: chrono::DateTime<chrono::Utc>,
+    /// When this attempt started
+    pub started_at: chrono::DateTime<chrono::Utc>,
+
+    /// How long to wait for the endpoint, when the task overrides the defaults
+    timeout_ms: Option<i64>,
+
+    /// Sent with every invocation, on top of the endpoint's credentials
+    pub headers: sqlx::types::Json<HashMap<String, String>>,
+
+    /// How to read the endpoint's response, when the task overrides the job default
+    response_mode: Option<String>,
+
+    /// Set before the queries that clean the task up, so one that fails
+    /// panics once, instead of again when the task is dropped
     cleaned_up: bool,
 }
 
+/// How an endpoint's response is read
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum ResponseMode {
+    /// The endpoint responds with a JSON `InvokedTaskResponse`
+    Strict,
+    /// Any 2xx response is a success, for endpoints that don't know about pointguard
+    Lenient,
+}
+
+impl ResponseMode {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "strict",
+            Self::Lenient => "lenient",
+        }
+    }
+
+    fn from_db(mode: &str) -> Self {
+        match mode {
+            "strict" => Self::Strict,
+            "lenient" => Self::Lenient,
+            _ => unreachable!("unknown response mode {mode:?}"),
+        }
+    }
+}
+
 enum RetryStatus {
     Retry,
     GiveUp,
@@ -32,13 +91,25 @@ impl Display for RetryStatus {
 }
 
 impl InflightTask {
+    /// How long to wait for the endpoint to respond, if the task was enqueued with a timeout
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        self.timeout_ms
+            .map(|ms| std::time::Duration::from_millis(ms as u64))
+    }
+
+    /// How to read the endpoint's response, if the task was enqueued with a response mode
+    pub fn response_mode(&self) -> Option<ResponseMode> {
+        self.response_mode.as_deref().map(ResponseMode::from_db)
+    }
+
     pub async fn done(mut self, conn: &sqlx::PgPool) {
+        self.cleaned_up = true;
         let mut tx = conn.begin().await.expect("failed to start transaction");
         sqlx::query!(
             "
             INSERT INTO finished_tasks
-            (job_name, data, endpoint, name, retries, started_at, task_created_at)
-            SELECT job_name, data, endpoint, name, retry_count, started_at, created_at
+            (task_id, job_name, data, endpoint, name, retries, started_at, task_created_at)
+            SELECT id, job_name, data, endpoint, name, retry_count, started_at, created_at
             FROM tasks WHERE id = $1
             ",
             self.id,
@@ -50,14 +121,149 @@ impl InflightTask {
             .execute(&mut *tx)
             .await
             .expect("failed to delete task");
+        notify_finished(&mut tx, self.id)
+            .await
+            .expect("failed to notify finished task");
 
+        // TODO: maybe we should have a global error handler that will retry?
         tx.commit().await.expect("failed to commit transaction");
+    }
 
-        // TODO: maybe we should have a global error handler that will retry?
+    /// Records a task that was cancelled while running
+    pub async fn cancelled(mut self, conn: &sqlx::PgPool) {
+        self.cleaned_up = true;
+        let mut tx = conn.begin().await.expect("failed to start transaction");
+        sqlx::query!(
+            "
+            INSERT INTO finished_tasks
+            (task_id, job_name, data, endpoint, name, retries, started_at, task_created_at, error_message, cancelled)
+            SELECT id, job_name, data, endpoint, name, retry_count, started_at, created_at, 'cancelled', true
+            FROM tasks WHERE id = $1
+            ",
+            self.id,
+        )
+        .execute(&mut *tx)
+        .await
+        .expect("failed to insert cancelled task");
+        sqlx::query!("DELETE FROM tasks WHERE id = $1", self.id)
+            .execute(&mut *tx)
+            .await
+            .expect("failed to delete task");
+        notify_finished(&mut tx, self.id)
+            .await
+            .expect("failed to notify finished task");
+
+        tx.commit().await.expect("failed to commit transaction");
+    }
+
+    /// The endpoint will finish the task on its own and report back with the attempt token.
+    /// Until then the task stays out of the queue, and it is retried if the lease expires.
+    /// Does nothing if the endpoint already reported back on this attempt.
+    ///
+    /// `lease` should be at most [`MAX_LEASE`].
+    pub async fn accepted(mut self, conn: &sqlx::PgPool, lease: std::time::Duration) {
+        self.cleaned_up = true;
+        sqlx::query!(
+            "
+            UPDATE
+                tasks
+            SET
+                lease_duration = $2,
+                -- cancelled while the endpoint was accepting it, so the worker
+                -- that takes over the expired lease records it as cancelled
+                lease_expires_at = CASE WHEN cancel_requested_at IS NULL THEN now() + $2 ELSE now() END,
+                updated_at = now()
+            WHERE
+                id = $1
+                -- still taken by this attempt, and not by the endpoint reporting back
+                AND attempt_id = $3
+                AND attempt_token_hash IS NOT NULL
+            ",
+            self.id,
+            crate::interval(lease) as std::time::Duration,
+            self.attempt_id,
+        )
+        .execute(conn)
+        .await
+        .expect("failed to accept task");
+    }
+
+    /// Gives the task back to the queue without counting an attempt,
+    /// for when we stop working on it, like on shutdown.
+    pub async fn release(mut self, conn: &sqlx::PgPool) {
         self.cleaned_up = true;
+        sqlx::query!(
+            "
+            UPDATE
+                tasks
+            SET
+                -- also clears the attempt, see pointguard_end_attempt()
+                worker_id = NULL,
+                updated_at = now()
+            WHERE
+                id = $1
+            RETURNING
+                pg_notify(pointguard_channel($2), json_build_object('run_at', run_at, 'id', id)::text)
+            ",
+            self.id,
+            constants::NEW_TASK_QUEUE,
+        )
+        .execute(conn)
+        .await
+        .expect("failed to release task");
     }
 
-    pub async fn failed(mut self, conn: &sqlx::PgPool, message: &str, retriable: bool) {
+    /// Puts the task back in the queue to run at `run_at`, without counting as a retry,
+    /// and with new input data if the endpoint gave one
+    pub async fn rescheduled(
+        mut self,
+        conn: &sqlx::PgPool,
+        run_at: chrono::DateTime<chrono::Utc>,
+        data: Option<&serde_json::Value>,
+    ) {
+        self.cleaned_up = true;
+        let rescheduled = sqlx::query!(
+            "
+            UPDATE
+                tasks
+            SET
+                -- also clears the attempt, see pointguard_end_attempt()
+                worker_id = NULL,
+                run_at = $3,
+                data = COALESCE($4, data),
+                snooze_count = snooze_count + 1,
+                updated_at = now()
+            WHERE
+                id = $1
+                AND cancel_requested_at IS NULL
+            RETURNING
+                pg_notify(pointguard_channel($2), json_build_object('run_at', run_at, 'id', id)::text)
+            ",
+            self.id,
+            constants::NEW_TASK_QUEUE,
+            run_at,
+            data,
+        )
+        .fetch_optional(conn)
+        .await
+        .expect("failed to reschedule task");
+
+        // it was cancelled while the endpoint was deciding to reschedule it
+        if rescheduled.is_none() {
+            self.cancelled(conn).await;
+        }
+    }
+
+    /// Retries the task after `retry_after`, or after its retry delay,
+    /// unless it isn't retriable or is out of retries
+    pub async fn failed(
+        mut self,
+        conn: &sqlx::PgPool,
+        message: &str,
+        retriable: bool,
+        retry_after: Option<std::time::Duration>,
+    ) {
+        self.cleaned_up = true;
         let status = if retriable && self.max_retries > self.retry_count {
             RetryStatus::Retry
         } else if retriable {
@@ -67,24 +273,34 @@ impl InflightTask {
         };
 
         if let RetryStatus::Retry = status {
-            sqlx::query!(
+            let retried = sqlx::query!(
                 "
                 UPDATE
                     tasks
                 SET
+                    -- also clears the attempt, see pointguard_end_attempt()
                     worker_id = NULL,
-                    started_at = NULL,
-                    run_at = now() + retry_delay,
+                    run_at = now() + COALESCE($3, retry_delay),
                     updated_at = now(),
                     retry_count = retry_count + 1
                 WHERE
                     id = $1
+                    AND cancel_requested_at IS NULL
+                RETURNING
+                    pg_notify(pointguard_channel($2), json_build_object('run_at', run_at, 'id', id)::text)
             ",
                 self.id,
+                constants::NEW_TASK_QUEUE,
+                retry_after.map(crate::interval) as Option<std::time::Duration>,
             )
-            .execute(conn)
+            .fetch_optional(conn)
             .await
             .expect("failed to update task");
+
+            // it failed before we could stop it, but it was still cancelled
+            if retried.is_none() {
+                self.cancelled(conn).await;
+            }
         } else {
             tracing::error!(
                 "task {} failed {} times, {status}",
@@ -95,8 +311,8 @@ impl InflightTask {
             sqlx::query!(
                 "
                 INSERT INTO finished_tasks
-                (job_name, data, endpoint, name, retries, started_at, task_created_at, error_message)
-                SELECT job_name, data, endpoint, name, retry_count, started_at, created_at, $2
+                (task_id, job_name, data, endpoint, name, retries, started_at, task_created_at, error_message)
+                SELECT id, job_name, data, endpoint, name, retry_count, started_at, created_at, $2
                 FROM tasks WHERE id = $1
                 ",
                 self.id,
@@ -115,13 +331,30 @@ impl InflightTask {
             .execute(&mut *tx)
             .await
             .expect("failed to delete task");
+            notify_finished(&mut tx, self.id)
+                .await
+                .expect("failed to notify finished task");
             tx.commit().await.expect("failed to commit transaction");
         }
-
-        self.cleaned_up = true;
     }
 }
 
+/// Tells listeners (see [`crate::FinishedTaskListener`]) that a task reached a terminal state.
+/// The notification is only delivered once the transaction commits.
+async fn notify_finished(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    task_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "SELECT pg_notify(pointguard_channel($1), json_build_object('id', $2::bigint)::text)",
+        constants::FINISHED_TASK_QUEUE,
+        task_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
 impl Drop for InflightTask {
     fn drop(&mut self) {
         if !self.cleaned_up {
@@ -130,27 +363,317 @@ impl Drop for InflightTask {
     }
 }
 
-pub async fn free_tasks(db: &sqlx::PgPool, count: i64) -> Result<Vec<InflightTask>, sqlx::Error> {
-    let mut tx = db.begin().await.unwrap();
-    let inflight_tasks = sqlx::query_as!(
+/// The longest an endpoint can hold on to an accepted task without a heartbeat
+pub const MAX_LEASE: std::time::Duration = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Takes over a task by the token of its current attempt, for endpoints that report it finished.
+/// That can be before the worker saw the endpoint accept it, since the response may still be
+/// on its way. Cancelled tasks are finished by the worker that takes over their lease instead.
+///
+/// The token can only be used once, and the lease stops, so the task is ours to finish.
+pub async fn take_attempted_task(
+    db: &sqlx::PgPool,
+    id: i64,
+    attempt_token: &str,
+) -> Result<Option<InflightTask>, sqlx::Error> {
+    sqlx::query_as!(
         InflightTask,
         "
-        SELECT id, created_at, job_name, data, endpoint, name, false as \"cleaned_up!\", max_retries, retry_count
+        UPDATE tasks
+        SET
+            worker_id = current_setting('application_name'),
+            attempt_token_hash = NULL,
+            lease_expires_at = NULL,
+            updated_at = now()
+        WHERE id = $1
+          AND attempt_token_hash = encode(sha256(convert_to($2, 'UTF8')), 'hex')
+          AND cancel_requested_at IS NULL
+        RETURNING id, created_at, job_name, data, endpoint, name, false as \"cleaned_up!\", max_retries, retry_count, snooze_count, cancel_endpoint, cancel_requested_at IS NOT NULL as \"cancel_requested!\", (EXTRACT(EPOCH FROM timeout) * 1000)::bigint as timeout_ms, headers as \"headers: sqlx::types::Json<HashMap<String, String>>\", response_mode, $2 as \"attempt_token!\", COALESCE(attempt_id, '') as \"attempt_id!\", run_at as scheduled_for, COALESCE(started_at, now()) as \"started_at!\"
+        ",
+        id,
+        attempt_token,
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Pushes the lease of an accepted task forward, by `lease` or by the lease it was accepted with.
+/// Returns whether the attempt token matched an accepted task that wasn't cancelled.
+///
+/// `lease` should be at most [`MAX_LEASE`].
+pub async fn extend_lease(
+    db: &sqlx::PgPool,
+    id: i64,
+    attempt_token: &str,
+    lease: Option<std::time::Duration>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "
+        UPDATE tasks
+        SET
+            lease_duration = COALESCE($3, lease_duration),
+            lease_expires_at = now() + COALESCE($3, lease_duration),
+            updated_at = now()
+        WHERE id = $1
+          AND attempt_token_hash = encode(sha256(convert_to($2, 'UTF8')), 'hex')
+          AND lease_expires_at IS NOT NULL
+          AND cancel_requested_at IS NULL
+        ",
+        id,
+        attempt_token,
+        lease.map(crate::interval) as Option<std::time::Duration>,
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Stores the progress the endpoint reported on its current attempt.
+/// Returns whether the attempt token matched.
+pub async fn report_progress(
+    db: &sqlx::PgPool,
+    id: i64,
+    attempt_token: &str,
+    percent: Option<f32>,
+    message: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "
+        UPDATE tasks
+        SET
+            progress_percent = $3,
+            progress_message = $4,
+            progress_updated_at = now()
+        WHERE id = $1
+          AND attempt_token_hash = encode(sha256(convert_to($2, 'UTF8')), 'hex')
+        ",
+        id,
+        attempt_token,
+        percent,
+        message,
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Takes over accepted tasks whose lease expired, so they can be failed and retried
+pub async fn expired_leases(db: &sqlx::PgPool) -> Result<Vec<InflightTask>, sqlx::Error> {
+    sqlx::query_as!(
+        InflightTask,
+        "
+        UPDATE tasks
+        SET
+            worker_id = current_setting('application_name'),
+            attempt_token_hash = NULL,
+            attempt_id = NULL,
+            lease_expires_at = NULL,
+            progress_percent = NULL,
+            progress_message = NULL,
+            progress_updated_at = NULL,
+            updated_at = now()
+        WHERE id IN (
+            SELECT id
+            FROM tasks
+            WHERE lease_expires_at < now()
+            FOR UPDATE
+            SKIP LOCKED
+        )
+        RETURNING id, created_at, job_name, data, endpoint, name, false as \"cleaned_up!\", max_retries, retry_count, snooze_count, cancel_endpoint, cancel_requested_at IS NOT NULL as \"cancel_requested!\", (EXTRACT(EPOCH FROM timeout) * 1000)::bigint as timeout_ms, headers as \"headers: sqlx::types::Json<HashMap<String, String>>\", response_mode, '' as \"attempt_token!\", COALESCE(attempt_id, '') as \"attempt_id!\", run_at as scheduled_for, COALESCE(started_at, now()) as \"started_at!\"
+        "
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// The tasks this worker is running that were asked to be cancelled
+pub async fn cancel_requested_tasks(db: &sqlx::PgPool) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "
+        SELECT id
+        FROM tasks
+        WHERE worker_id = current_setting('application_name')
+          AND cancel_requested_at IS NOT NULL
+        "
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// When the next task that isn't being worked on is due, so we can sleep until then
+pub async fn next_run_at(
+    db: &sqlx::PgPool,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "
+        SELECT min(run_at)
         FROM tasks
         LEFT JOIN running_workers ON tasks.worker_id = running_workers.application_name
         WHERE running_workers.application_name IS NULL
-          AND run_at <= NOW()
-        FOR UPDATE of tasks
-        SKIP LOCKED
-        LIMIT $1
-        ",
-        count,
+          AND lease_expires_at IS NULL
+        "
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Claims up to `count` due tasks for this worker.
+///
+/// Tasks under a concurrency limit are claimed while holding an advisory lock per limit,
+/// so two workers can't both take the last slot. Claims that don't involve the same limits,
+/// and changes to the limits themselves, don't wait on each other.
+pub async fn free_tasks(db: &sqlx::PgPool, count: i64) -> Result<Vec<InflightTask>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    // the limits that could hold back a due task. Reading them doesn't lock anything,
+    // so the claims below only take limited tasks under the limits locked here
+    let mut lock_keys = sqlx::query_scalar!(
+        "
+        SELECT hashtext(current_schema() || ':' || scope || ':' || key) AS \"lock_key!\"
+        FROM concurrency_limits
+        WHERE EXISTS (
+            SELECT 1
+            FROM tasks
+            LEFT JOIN running_workers ON tasks.worker_id = running_workers.application_name
+            WHERE running_workers.application_name IS NULL
+              AND lease_expires_at IS NULL
+              AND run_at <= NOW()
+              AND (
+                (concurrency_limits.scope = 'job_name' AND tasks.job_name = concurrency_limits.key)
+                OR (concurrency_limits.scope = 'endpoint_host' AND tasks.endpoint_host = concurrency_limits.key)
+              )
+        )
+        "
     )
     .fetch_all(&mut *tx)
     .await?;
+    // in the same order on every worker, so they can't deadlock
+    lock_keys.sort_unstable();
+    lock_keys.dedup();
+    if !lock_keys.is_empty() {
+        sqlx::query!(
+            "SELECT pg_advisory_xact_lock($1, lock_key) FROM unnest($2::int[]) AS lock_key",
+            constants::CONCURRENCY_LIMIT_LOCK,
+            &lock_keys,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let mut inflight_tasks = if lock_keys.is_empty() {
+        sqlx::query_as!(
+            InflightTask,
+            "
+            SELECT id, created_at, job_name, data, endpoint, name, false as \"cleaned_up!\", max_retries, retry_count, snooze_count, cancel_endpoint, cancel_requested_at IS NOT NULL as \"cancel_requested!\", (EXTRACT(EPOCH FROM timeout) * 1000)::bigint as timeout_ms, headers as \"headers: sqlx::types::Json<HashMap<String, String>>\", response_mode, '' as \"attempt_token!\", '' as \"attempt_id!\", run_at as scheduled_for, now() as \"started_at!\"
+            FROM tasks
+            LEFT JOIN running_workers ON tasks.worker_id = running_workers.application_name
+            WHERE running_workers.application_name IS NULL
+              AND lease_expires_at IS NULL
+              AND run_at <= NOW()
+              -- a limit added since we looked waits for the next claim
+              AND NOT EXISTS (
+                SELECT 1
+                FROM concurrency_limits
+                WHERE (scope = 'job_name' AND key = tasks.job_name)
+                   OR (scope = 'endpoint_host' AND key = tasks.endpoint_host)
+              )
+            FOR UPDATE of tasks
+            SKIP LOCKED
+            LIMIT $1
+            ",
+            count,
+        )
+        .fetch_all(&mut *tx)
+        .await?
+    } else {
+        // only take as many tasks as there are free slots left under each limit.
+        // Tasks are ranked by host among those whose job has room, then by job among
+        // those their host has room for, so a task held back by one limit doesn't
+        // take a slot under the other
+        sqlx::query_as!(
+            InflightTask,
+            "
+            WITH running AS (
+                SELECT tasks.job_name, tasks.endpoint_host
+                FROM tasks
+                LEFT JOIN running_workers ON tasks.worker_id = running_workers.application_name
+                WHERE running_workers.application_name IS NOT NULL
+                   OR tasks.lease_expires_at IS NOT NULL
+            ),
+            candidates AS (
+                SELECT
+                    tasks.id,
+                    tasks.job_name,
+                    tasks.endpoint_host,
+                    tasks.run_at,
+                    job_limit.max_concurrency
+                        - (SELECT count(*) FROM running WHERE running.job_name = tasks.job_name) AS job_slots,
+                    host_limit.max_concurrency
+                        - (SELECT count(*) FROM running WHERE running.endpoint_host = tasks.endpoint_host) AS host_slots
+                FROM tasks
+                LEFT JOIN running_workers ON tasks.worker_id = running_workers.application_name
+                LEFT JOIN concurrency_limits job_limit
+                    ON job_limit.scope = 'job_name' AND job_limit.key = tasks.job_name
+                LEFT JOIN concurrency_limits host_limit
+                    ON host_limit.scope = 'endpoint_host' AND host_limit.key = tasks.endpoint_host
+                WHERE running_workers.application_name IS NULL
+                  AND lease_expires_at IS NULL
+                  AND run_at <= NOW()
+                  -- a limit added since we looked waits for the next claim
+                  AND (
+                    job_limit.key IS NULL
+                    OR hashtext(current_schema() || ':' || job_limit.scope || ':' || job_limit.key) = ANY($2)
+                  )
+                  AND (
+                    host_limit.key IS NULL
+                    OR hashtext(current_schema() || ':' || host_limit.scope || ':' || host_limit.key) = ANY($2)
+                  )
+            ),
+            host_ranked AS (
+                SELECT
+                    candidates.*,
+                    row_number() OVER (PARTITION BY endpoint_host ORDER BY run_at, id) AS host_rank
+                FROM candidates
+                WHERE job_slots IS NULL OR job_slots > 0
+            ),
+            job_ranked AS (
+                SELECT
+                    host_ranked.*,
+                    row_number() OVER (PARTITION BY job_name ORDER BY run_at, id) AS job_rank
+                FROM host_ranked
+                WHERE host_slots IS NULL OR host_rank <= host_slots
+            )
+            SELECT id, created_at, job_name, data, endpoint, name, false as \"cleaned_up!\", max_retries, retry_count, snooze_count, cancel_endpoint, cancel_requested_at IS NOT NULL as \"cancel_requested!\", (EXTRACT(EPOCH FROM timeout) * 1000)::bigint as timeout_ms, headers as \"headers: sqlx::types::Json<HashMap<String, String>>\", response_mode, '' as \"attempt_token!\", '' as \"attempt_id!\", run_at as scheduled_for, now() as \"started_at!\"
+            FROM tasks
+            WHERE id IN (
+                SELECT id
+                FROM job_ranked
+                WHERE job_slots IS NULL OR job_rank <= job_slots
+            )
+            ORDER BY run_at
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT $1
+            ",
+            count,
+            &lock_keys,
+        )
+        .fetch_all(&mut *tx)
+        .await?
+    };
 
     if !inflight_tasks.is_empty() {
+        // only the hash is stored, the token itself is given to the endpoint
+        for task in &mut inflight_tasks {
+            task.attempt_token = nanoid::nanoid!(32);
+            task.attempt_id = nanoid::nanoid!();
+        }
         let ids: Vec<i64> = inflight_tasks.iter().map(|t| t.id).collect();
+        let tokens: Vec<&str> = inflight_tasks
+            .iter()
+            .map(|t| &t.attempt_token[..])
+            .collect();
+        let attempt_ids: Vec<&str> = inflight_tasks.iter().map(|t| &t.attempt_id[..]).collect();
         sqlx::query!(
             "
             UPDATE
@@ -158,11 +681,17 @@ pub async fn free_tasks(db: &sqlx::PgPool, count: i64) -> Result<Vec<InflightTas
             SET
                 worker_id = current_setting('application_name'),
                 started_at = now(),
-                updated_at = now()
+                updated_at = now(),
+                attempt_token_hash = encode(sha256(convert_to(attempts.token, 'UTF8')), 'hex'),
+                attempt_id = attempts.attempt_id
+            FROM
+                unnest($1::bigint[], $2::text[], $3::text[]) AS attempts(id, token, attempt_id)
             WHERE
-                id = ANY($1)
+                tasks.id = attempts.id
             ",
             &ids,
+            &tokens as &[&str],
+            &attempt_ids as &[&str],
         )
         .execute(&mut *tx)
         .await?;
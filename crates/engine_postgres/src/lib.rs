@@ -1,17 +1,32 @@
+mod archive;
+mod concurrency_limits;
 mod constants;
+mod header_encryption;
 mod inflight_task;
+mod migrations;
+mod partitions;
 mod task_listener;
 
+pub use archive::*;
+pub use concurrency_limits::*;
+pub use header_encryption::*;
 pub use inflight_task::*;
-pub use sqlx::postgres;
+pub use migrations::*;
+pub use partitions::*;
+pub use sqlx::{postgres, Error};
 use sqlx::{Executor, PgPool};
-use std::{num::NonZeroU32, str::FromStr};
-pub use task_listener::{NewTaskPayload, TaskListener};
+use std::{collections::HashMap, num::NonZeroU32, str::FromStr};
+pub use task_listener::{
+    CancelTaskListener, CancelTaskPayload, FinishedTaskListener, FinishedTaskPayload,
+    ListenerHealth, ListenerStatus, NewTaskPayload, TaskListener, TaskWakeup, LISTENER_CONNECTIONS,
+};
 
 #[derive(Debug, serde::Serialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct FinishedTask {
     pub id: i64,
+    /// The ID the task had while it was enqueued
+    pub task_id: Option<i64>,
     pub job_name: String,
     pub name: String,
     pub endpoint: String,
@@ -20,6 +35,8 @@ pub struct FinishedTask {
     pub started_at: chrono::DateTime<chrono::Utc>,
     pub data: serde_json::Value,
     pub retries: i32,
+    /// Whether the task was cancelled while it was running
+    pub cancelled: bool,
 }
 
 #[derive(Debug, serde::Serialize, schemars::JsonSchema)]
@@ -35,6 +52,8 @@ pub struct EnqueuedTask {
     pub retry_count: i32,
     pub max_retries: i32,
     pub worker_id: Option<String>,
+    /// The names of the headers sent with every invocation. Their values are never returned.
+    pub header_names: Vec<String>,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, schemars::JsonSchema, Default)]
@@ -43,6 +62,19 @@ pub struct PaginationCursor {
     pub limit: Option<i32>,
 }
 
+/// Limits the finished tasks to a time range,
+/// so only the relevant partitions of `finished_tasks` are scanned.
+#[derive(Debug, serde::Deserialize, serde::Serialize, schemars::JsonSchema, Default)]
+pub struct FinishedTasksRange {
+    /// Only include tasks that finished at or after this time
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only include tasks that finished before this time
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Removes a task that isn't running. If it is running, asks the worker running it to stop,
+/// and the worker records it as cancelled in `finished_tasks`. If it was accepted by its endpoint,
+/// its lease expires right away, and the worker that takes it over records it as cancelled.
 pub async fn cancel_task(db: &PgPool, id: i64) -> Result<Option<i64>, sqlx::Error> {
     let task = sqlx::query!(
         "
@@ -54,10 +86,33 @@ pub async fn cancel_task(db: &PgPool, id: i64) -> Result<Option<i64>, sqlx::Erro
             WHERE
                 id = $1
                 AND running_workers.application_name IS NULL
+                AND tasks.lease_expires_at IS NULL
         )
-        RETURNING id
+        -- wakes up anyone waiting for the task, see `/wait`
+        RETURNING id, pg_notify(pointguard_channel($2), json_build_object('id', id)::text)
         ",
-        id
+        id,
+        constants::FINISHED_TASK_QUEUE,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    if let Some(task) = task {
+        return Ok(Some(task.id));
+    }
+
+    let task = sqlx::query!(
+        "
+        UPDATE tasks
+        SET
+            cancel_requested_at = COALESCE(cancel_requested_at, now()),
+            lease_expires_at = CASE WHEN lease_expires_at IS NOT NULL THEN now() END,
+            updated_at = now()
+        WHERE id = $1 AND worker_id IS NOT NULL
+        RETURNING id, pg_notify(pointguard_channel($2), json_build_object('id', id)::text)
+        ",
+        id,
+        constants::CANCEL_TASK_QUEUE,
     )
     .fetch_optional(db)
     .await?;
@@ -79,7 +134,8 @@ pub async fn enqueued_tasks(db: &PgPool) -> Result<Vec<EnqueuedTask>, sqlx::Erro
             run_at,
             retry_count,
             max_retries,
-            worker_id
+            worker_id,
+            ARRAY(SELECT jsonb_object_keys(headers)) as \"header_names!\"
         FROM
             tasks
         LEFT OUTER JOIN
@@ -107,8 +163,9 @@ pub async fn unshift_job(db: &PgPool, task_id: i64) -> Result<Option<i64>, sqlx:
             WHERE
                 id = $1
                 AND running_workers.application_name IS NULL
+                AND tasks.lease_expires_at IS NULL
         )
-        RETURNING id, pg_notify($2, json_build_object('run_at', run_at, 'id', id)::text)
+        RETURNING id, pg_notify(pointguard_channel($2), json_build_object('run_at', run_at, 'id', id)::text)
         ",
         task_id,
         constants::NEW_TASK_QUEUE,
@@ -134,17 +191,30 @@ pub struct Paginated<T> {
 pub async fn finished_tasks(
     db: &PgPool,
     cursor: &PaginationCursor,
+    range: &FinishedTasksRange,
 ) -> Result<Paginated<FinishedTask>, sqlx::Error> {
     let limit = cursor.limit.unwrap_or(100) + 1;
     let offset = cursor.page.map_or(0, |p| (p.get() - 1) * limit as u32) as i64;
 
-    let count =
-        sqlx::query_scalar!("SELECT COUNT(*) as \"count!\" FROM finished_tasks").fetch_one(db);
+    // COALESCE keeps the predicates prunable at execution time, unlike `$1 IS NULL OR ...`
+    let count = sqlx::query_scalar!(
+        "
+        SELECT COUNT(*) as \"count!\"
+        FROM finished_tasks
+        WHERE
+            created_at >= COALESCE($1, '-infinity'::timestamptz)
+            AND created_at < COALESCE($2, 'infinity'::timestamptz)
+        ",
+        range.since,
+        range.until,
+    )
+    .fetch_one(db);
     let items = sqlx::query_as!(
         FinishedTask,
         "
         SELECT
             id,
+            task_id,
             job_name,
             name,
             endpoint,
@@ -152,9 +222,13 @@ pub async fn finished_tasks(
             error_message,
             created_at,
             data,
-            retries
+            retries,
+            cancelled
         FROM
             finished_tasks
+        WHERE
+            created_at >= COALESCE($3, '-infinity'::timestamptz)
+            AND created_at < COALESCE($4, 'infinity'::timestamptz)
         ORDER BY
             created_at DESC
         LIMIT $1::int
@@ -162,6 +236,8 @@ pub async fn finished_tasks(
         ",
         limit,
         offset.into(),
+        range.since,
+        range.until,
     )
     .fetch_all(db);
 
@@ -178,7 +254,48 @@ pub async fn finished_tasks(
     })
 }
 
-#[derive(serde::Serialize, Debug)]
+/// Finds the terminal record of a task by the ID it had while enqueued.
+pub async fn finished_task(db: &PgPool, task_id: i64) -> Result<Option<FinishedTask>, sqlx::Error> {
+    sqlx::query_as!(
+        FinishedTask,
+        "
+        SELECT
+            id,
+            task_id,
+            job_name,
+            name,
+            endpoint,
+            started_at,
+            error_message,
+            created_at,
+            data,
+            retries,
+            cancelled
+        FROM
+            finished_tasks
+        WHERE
+            task_id = $1
+        ORDER BY
+            created_at DESC
+        LIMIT 1
+        ",
+        task_id,
+    )
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn is_enqueued(db: &PgPool, id: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM tasks WHERE id = $1) as \"exists!\"",
+        id
+    )
+    .fetch_one(db)
+    .await
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct OngoingTask {
     pub id: i64,
     pub created_at: chrono::DateTime<chrono::Utc>,
@@ -188,11 +305,24 @@ pub struct OngoingTask {
     pub name: String,
     pub started_at: chrono::DateTime<chrono::Utc>,
     pub worker_id: String,
+    /// Identifies the current attempt, like the `attemptId` the endpoint was invoked with
+    pub attempt_id: Option<String>,
 
     pub max_retries: i32,
     pub retry_count: i32,
+
+    /// When the task is retried, if it was accepted by its endpoint
+    pub lease_expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// How far along the task is, from 0 to 100, as reported by the endpoint
+    pub progress_percent: Option<f32>,
+    /// What the task is doing, as reported by the endpoint
+    pub progress_message: Option<String>,
+    pub progress_updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The names of the headers sent with every invocation. Their values are never returned.
+    pub header_names: Vec<String>,
 }
 
+/// Tasks being run by a live worker, or accepted by their endpoint
 pub async fn ongoing_tasks(db: &sqlx::PgPool) -> Result<Vec<OngoingTask>, sqlx::Error> {
     let tasks = sqlx::query_as!(
         OngoingTask,
@@ -207,9 +337,18 @@ pub async fn ongoing_tasks(db: &sqlx::PgPool) -> Result<Vec<OngoingTask>, sqlx::
             \"started_at\" as \"started_at!\",
             max_retries,
             retry_count,
-            worker_id as \"worker_id!\"
+            worker_id as \"worker_id!\",
+            attempt_id,
+            lease_expires_at,
+            progress_percent,
+            progress_message,
+            progress_updated_at,
+            ARRAY(SELECT jsonb_object_keys(headers)) as \"header_names!\"
         FROM tasks
-        JOIN running_workers ON tasks.worker_id = running_workers.application_name
+        LEFT JOIN running_workers ON tasks.worker_id = running_workers.application_name
+        WHERE running_workers.application_name IS NOT NULL
+           OR tasks.lease_expires_at IS NOT NULL
+        ORDER BY started_at
         "
     )
     .fetch_all(db)
@@ -227,22 +366,28 @@ pub struct NewTask {
     pub run_at: Option<chrono::DateTime<chrono::Utc>>,
 
     pub max_retries: Option<i32>,
+    /// Notified when the task is cancelled while running
+    pub cancel_endpoint: Option<String>,
+    /// How long to wait for the endpoint to respond, instead of the worker's default
+    pub timeout: Option<std::time::Duration>,
+    /// Sent with every invocation
+    pub headers: HashMap<String, String>,
+    /// How to read the endpoint's response, instead of the job default
+    pub response_mode: Option<ResponseMode>,
 }
 
 pub async fn enqueue(db: &sqlx::PgPool, task: &NewTask) -> Result<i64, sqlx::Error> {
     let id = sqlx::query!(
         "
-        INSERT INTO tasks (job_name, data, endpoint, name, run_at, max_retries)
-        VALUES ($1, $2, $3, $4, COALESCE($5, now()), $6)
+        INSERT INTO tasks (job_name, data, endpoint, name, run_at, max_retries, cancel_endpoint, timeout, headers, response_mode)
+        VALUES ($1, $2, $3, $4, COALESCE($5, now()), $6, $8, $9, $10, $11)
         ON CONFLICT (job_name, name, endpoint) DO UPDATE
         SET
             updated_at = now()
         RETURNING
             id,
-            CASE WHEN run_at <= NOW()
-                THEN pg_notify($7, json_build_object('run_at', run_at, 'id', id)::text)
-                ELSE null
-            END AS \"notify\"
+            -- delayed tasks notify too, so workers can wake up right when they are due
+            pg_notify(pointguard_channel($7), json_build_object('run_at', run_at, 'id', id)::text)
         ",
         task.job_name,
         task.data,
@@ -251,6 +396,10 @@ pub async fn enqueue(db: &sqlx::PgPool, task: &NewTask) -> Result<i64, sqlx::Err
         task.run_at,
         task.max_retries.unwrap_or(0) as i64,
         constants::NEW_TASK_QUEUE,
+        task.cancel_endpoint,
+        task.timeout.map(interval) as Option<std::time::Duration>,
+        sqlx::types::Json(&task.headers) as _,
+        task.response_mode.map(|mode| mode.as_str()),
     )
     .fetch_one(db)
     .await?;
@@ -261,47 +410,134 @@ pub async fn enqueue(db: &sqlx::PgPool, task: &NewTask) -> Result<i64, sqlx::Err
 #[derive(Default)]
 pub struct DbOptions {
     pub schema: Option<String>,
+
+    /// The maximum number of connections in the pool
+    pub max_connections: Option<u32>,
+    /// The number of connections the pool tries to keep open
+    pub min_connections: Option<u32>,
+    /// How long to wait for a connection from the pool
+    pub acquire_timeout: Option<std::time::Duration>,
+    /// How long a connection can be idle before it is closed
+    pub idle_timeout: Option<std::time::Duration>,
+
+    /// Postgres `statement_timeout`, applied to every connection
+    pub statement_timeout: Option<std::time::Duration>,
+    /// Postgres `lock_timeout`, applied to every connection
+    pub lock_timeout: Option<std::time::Duration>,
+
+    /// Overrides the `sslmode` of the connection string
+    pub ssl_mode: Option<sqlx::postgres::PgSslMode>,
+    /// A CA certificate to verify the server with
+    pub ssl_root_cert: Option<std::path::PathBuf>,
+    /// A client certificate to authenticate with
+    pub ssl_client_cert: Option<std::path::PathBuf>,
+    /// The private key of `ssl_client_cert`
+    pub ssl_client_key: Option<std::path::PathBuf>,
+}
+
+impl DbOptions {
+    /// The statements we run on every new connection
+    fn session_setup(&self) -> String {
+        let mut setup = String::new();
+        if let Some(schema) = &self.schema {
+            setup += &format!("SET search_path = '{schema}';");
+        }
+        if let Some(timeout) = self.statement_timeout {
+            setup += &format!("SET statement_timeout = {};", timeout.as_millis());
+        }
+        if let Some(timeout) = self.lock_timeout {
+            setup += &format!("SET lock_timeout = {};", timeout.as_millis());
+        }
+        setup
+    }
 }
 
 pub async fn connect(url: &str, options: &DbOptions) -> Result<PgPool, sqlx::Error> {
-    let connection_opts = sqlx::postgres::PgConnectOptions::from_str(url)
+    pool_options(options)
+        .connect_with(connect_options(url, options))
+        .await
+}
+
+/// Like [`connect`], but doesn't open a connection until the pool is used.
+/// Useful for optional databases, like a read replica, that shouldn't stop us from starting.
+pub fn connect_lazy(url: &str, options: &DbOptions) -> PgPool {
+    pool_options(options).connect_lazy_with(connect_options(url, options))
+}
+
+/// Whether the error means we couldn't talk to the database at all,
+/// rather than the query itself failing
+pub fn is_connection_error(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        // cannot_connect_now, admin_shutdown, crash_shutdown
+        sqlx::Error::Database(err) => {
+            matches!(err.code().as_deref(), Some("57P03" | "57P01" | "57P02"))
+        }
+        _ => false,
+    }
+}
+
+/// The longest interval we store. Callers validate against much shorter limits,
+/// this only keeps a stray duration from overflowing the microseconds sqlx encodes.
+const MAX_INTERVAL: std::time::Duration = std::time::Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Postgres intervals only go down to microseconds, and sqlx refuses to round.
+/// Durations longer than [`MAX_INTERVAL`] are clamped to it.
+pub(crate) fn interval(duration: std::time::Duration) -> std::time::Duration {
+    let micros = duration.min(MAX_INTERVAL).as_micros();
+    std::time::Duration::from_micros(u64::try_from(micros).unwrap_or(u64::MAX))
+}
+
+fn connect_options(url: &str, options: &DbOptions) -> sqlx::postgres::PgConnectOptions {
+    let mut connection_opts = sqlx::postgres::PgConnectOptions::from_str(url)
         .expect("parse db url")
         .application_name(&format!("pointguard:{}", nanoid::nanoid!()));
-    let mut pgpool_options = sqlx::postgres::PgPoolOptions::new();
 
-    if let Some(schema) = options.schema.clone() {
+    if let Some(ssl_mode) = options.ssl_mode {
+        connection_opts = connection_opts.ssl_mode(ssl_mode);
+    }
+    if let Some(root_cert) = &options.ssl_root_cert {
+        connection_opts = connection_opts.ssl_root_cert(root_cert);
+    }
+    if let Some(client_cert) = &options.ssl_client_cert {
+        connection_opts = connection_opts.ssl_client_cert(client_cert);
+    }
+    if let Some(client_key) = &options.ssl_client_key {
+        connection_opts = connection_opts.ssl_client_key(client_key);
+    }
+
+    connection_opts
+}
+
+fn pool_options(options: &DbOptions) -> sqlx::postgres::PgPoolOptions {
+    let mut pgpool_options =
+        sqlx::postgres::PgPoolOptions::new().min_connections(options.min_connections.unwrap_or(0));
+
+    // sqlx closes connections idle for 10 minutes unless told otherwise
+    if let Some(idle_timeout) = options.idle_timeout {
+        pgpool_options = pgpool_options.idle_timeout(idle_timeout);
+    }
+    if let Some(max_connections) = options.max_connections {
+        pgpool_options = pgpool_options.max_connections(max_connections);
+    }
+    if let Some(acquire_timeout) = options.acquire_timeout {
+        pgpool_options = pgpool_options.acquire_timeout(acquire_timeout);
+    }
+
+    let session_setup = options.session_setup();
+    if !session_setup.is_empty() {
         pgpool_options = pgpool_options.after_connect(move |conn, _| {
-            let schema = schema.to_string();
+            let session_setup = session_setup.clone();
             Box::pin(async move {
-                conn.execute(&format!("SET search_path = '{schema}';")[..])
-                    .await?;
+                conn.execute(&session_setup[..]).await?;
                 Ok(())
             })
         });
     }
 
-    pgpool_options.connect_with(connection_opts).await
-}
-
-pub async fn migrate(pool: &PgPool, options: &DbOptions) -> Result<(), sqlx::Error> {
-    let result = sqlx::migrate!().run(pool).await;
-
-    if let Some(schema) = options.schema.clone() {
-        if let Err(sqlx::migrate::MigrateError::Execute(sqlx::Error::Database(err))) =
-            result.as_ref()
-        {
-            if let Some(code) = err.code() {
-                if code == "3F000" {
-                    tracing::info!("schema {schema:?} not found. trying to create it.");
-                    pool.execute(&format!("CREATE SCHEMA {};", schema)[..])
-                        .await?;
-                    tracing::info!("schema {schema:?} created!");
-                    sqlx::migrate!().run(pool).await?;
-                    return Ok(());
-                }
-            }
-        }
-    }
-
-    result.map_err(|e| e.into())
+    pgpool_options
 }
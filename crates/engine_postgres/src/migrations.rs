@@ -0,0 +1,113 @@
+use crate::DbOptions;
+use sqlx::{
+    migrate::{Migrate, Migrator},
+    Executor, PgConnection, PgPool,
+};
+use std::collections::HashSet;
+
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// `invalid_schema_name`: the schema in the `search_path` does not exist
+const SCHEMA_NOT_FOUND: &str = "3F000";
+
+/// A connection without the statement timeout, since migrations can take a while.
+/// It is detached from the pool, so lifting the timeout doesn't leak to other queries.
+async fn migration_connection(pool: &PgPool) -> Result<PgConnection, sqlx::Error> {
+    let mut conn = pool.acquire().await?.detach();
+    conn.execute("SET statement_timeout = 0;").await?;
+    Ok(conn)
+}
+
+pub async fn migrate(pool: &PgPool, options: &DbOptions) -> Result<(), sqlx::Error> {
+    // use a single connection, so the migration lock is released on the same session
+    // that acquired it if we need to retry after creating the schema
+    let mut conn = migration_connection(pool).await?;
+    let result = MIGRATOR.run(&mut conn).await;
+
+    if let Some(schema) = options.schema.clone() {
+        if let Err(sqlx::migrate::MigrateError::Execute(sqlx::Error::Database(err))) =
+            result.as_ref()
+        {
+            if let Some(code) = err.code() {
+                if code == SCHEMA_NOT_FOUND {
+                    conn.unlock().await?;
+                    tracing::info!("schema {schema:?} not found. trying to create it.");
+                    conn.execute(&format!("CREATE SCHEMA {};", schema)[..])
+                        .await?;
+                    tracing::info!("schema {schema:?} created!");
+                    MIGRATOR.run(&mut conn).await?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    result.map_err(|e| e.into())
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// Lists every migration this binary knows about, and whether it was applied.
+pub async fn migration_status(pool: &PgPool) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+    let applied = applied_versions(pool).await?;
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.contains(&m.version),
+        })
+        .collect())
+}
+
+/// Returns the migrations that need to run before this binary can use the database.
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+    let mut statuses = migration_status(pool).await?;
+    statuses.retain(|m| !m.applied);
+    Ok(statuses)
+}
+
+/// Reverts applied migrations down to `target` (exclusive).
+/// When no target is given, only the latest applied migration is reverted.
+///
+/// Returns the versions that were reverted, latest first.
+pub async fn revert_migrations(
+    pool: &PgPool,
+    target: Option<i64>,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let mut applied: Vec<i64> = applied_versions(pool).await?.into_iter().collect();
+    applied.sort_unstable_by(|a, b| b.cmp(a));
+
+    let target = match target {
+        Some(target) => target,
+        None => applied.get(1).copied().unwrap_or(0),
+    };
+
+    let mut conn = migration_connection(pool).await?;
+    MIGRATOR.undo(&mut conn, target).await?;
+
+    Ok(applied.into_iter().filter(|v| *v > target).collect())
+}
+
+/// Doesn't create the migrations table, so looking at the status of a new database
+/// leaves it untouched. Every migration is pending until the table exists.
+async fn applied_versions(pool: &PgPool) -> Result<HashSet<i64>, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    // resolved through the `search_path`, like the migrator does
+    let exists =
+        sqlx::query_scalar!("SELECT to_regclass('_sqlx_migrations') IS NOT NULL as \"exists!\"")
+            .fetch_one(&mut *conn)
+            .await?;
+    if !exists {
+        return Ok(HashSet::new());
+    }
+    let applied = conn.list_applied_migrations().await?;
+    Ok(applied.into_iter().map(|m| m.version).collect())
+}
@@ -0,0 +1,152 @@
+//! `finished_tasks` is partitioned by day (UTC) on `created_at`.
+//! Partitions are created ahead of time and dropped once they expire.
+//! Tasks no daily partition covers go to the default partition, and are moved
+//! out when their partition is created.
+
+use crate::constants;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use sqlx::PgPool;
+
+const DEFAULT_PARTITION: &str = "finished_tasks_default";
+
+#[derive(Debug, Default)]
+pub struct PartitionMaintenance {
+    pub created: Vec<String>,
+    pub dropped: Vec<String>,
+    /// How many expired tasks were deleted from the default partition
+    pub pruned: u64,
+}
+
+struct Partition {
+    name: String,
+    /// `None` when the partition is unbounded from below
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl Partition {
+    fn covers(&self, at: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| from <= at) && self.to.is_none_or(|to| at < to)
+    }
+}
+
+/// Makes sure there are partitions for today and the next `days_ahead` days,
+/// and drops the partitions that only hold tasks older than `retention`.
+///
+/// Only one instance does this at a time; returns `None` if another one is already on it.
+pub async fn maintain_finished_tasks_partitions(
+    db: &PgPool,
+    days_ahead: u32,
+    retention: Option<std::time::Duration>,
+) -> Result<Option<PartitionMaintenance>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    let locked = sqlx::query_scalar!(
+        "SELECT pg_try_advisory_xact_lock($1, hashtext(current_schema())) as \"locked!\"",
+        constants::PARTITION_MAINTENANCE_LOCK,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !locked {
+        return Ok(None);
+    }
+
+    let partitions = sqlx::query!(
+        r#"
+        SELECT
+            c.relname as "name!",
+            substring(pg_get_expr(c.relpartbound, c.oid) from 'FROM \(''([^'']+)''\)')::timestamptz as "from?",
+            substring(pg_get_expr(c.relpartbound, c.oid) from 'TO \(''([^'']+)''\)')::timestamptz as "to?"
+        FROM pg_inherits i
+        JOIN pg_class c ON c.oid = i.inhrelid
+        WHERE i.inhparent = 'finished_tasks'::regclass
+          AND pg_get_expr(c.relpartbound, c.oid) <> 'DEFAULT'
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|p| Partition {
+        name: p.name,
+        from: p.from,
+        to: p.to,
+    })
+    .collect::<Vec<_>>();
+
+    let mut maintenance = PartitionMaintenance::default();
+    let today = Utc::now().date_naive();
+
+    for day in today.iter_days().take(days_ahead as usize + 1) {
+        let from = start_of(day);
+        if partitions.iter().any(|p| p.covers(from)) {
+            continue;
+        }
+        let name = format!("finished_tasks_p{}", day.format("%Y%m%d"));
+        let to = from + Duration::days(1);
+        // attaching fails if the default partition holds rows for the new one, so they are moved first
+        sqlx::query(&format!(
+            "CREATE TABLE \"{name}\" (LIKE finished_tasks INCLUDING DEFAULTS INCLUDING CONSTRAINTS)"
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "
+            WITH moved AS (
+                DELETE FROM {DEFAULT_PARTITION}
+                WHERE created_at >= $1 AND created_at < $2
+                RETURNING *
+            )
+            INSERT INTO \"{name}\" SELECT * FROM moved
+            "
+        ))
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "ALTER TABLE finished_tasks ATTACH PARTITION \"{name}\" FOR VALUES FROM ('{}') TO ('{}')",
+            from.to_rfc3339(),
+            to.to_rfc3339(),
+        ))
+        .execute(&mut *tx)
+        .await?;
+        maintenance.created.push(name);
+    }
+
+    if let Some(retention) = retention {
+        let retention = Duration::from_std(retention).unwrap_or(Duration::max_value());
+        let expired_before = Utc::now()
+            .checked_sub_signed(retention)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+        for partition in partitions {
+            if partition.to.is_some_and(|to| to <= expired_before) {
+                sqlx::query(&format!(
+                    "DROP TABLE \"{}\"",
+                    partition.name.replace('"', "\"\"")
+                ))
+                .execute(&mut *tx)
+                .await?;
+                maintenance.dropped.push(partition.name);
+            }
+        }
+
+        maintenance.pruned = sqlx::query(&format!(
+            "DELETE FROM {DEFAULT_PARTITION} WHERE created_at < $1"
+        ))
+        .bind(expired_before)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    }
+
+    tx.commit().await?;
+
+    Ok(Some(maintenance))
+}
+
+fn start_of(day: NaiveDate) -> DateTime<Utc> {
+    day.and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time")
+        .and_utc()
+}
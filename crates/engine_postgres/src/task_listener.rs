@@ -1,7 +1,26 @@
 use crate::constants;
+use sqlx::{postgres::PgListener, PgPool};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+/// How many pool connections a served database holds on to for as long as it is served:
+/// one each for the [`TaskListener`], the [`CancelTaskListener`] and the [`FinishedTaskListener`]
+/// behind `/wait`. `PgListener` takes its connection out of the pool, and never gives it back.
+pub const LISTENER_CONNECTIONS: u32 = 3;
+
+/// The shortest and longest we wait before reconnecting a [`TaskListener`]
+const RECONNECT_BACKOFF: (Duration, Duration) =
+    (Duration::from_millis(500), Duration::from_secs(30));
+
+/// Listens to new and finished tasks, reconnecting with backoff whenever the connection drops.
 pub struct TaskListener {
-    listener: sqlx::postgres::PgListener,
+    db: PgPool,
+    /// The listener, and the channel finished tasks are notified on
+    listener: Option<(PgListener, String)>,
+    health: ListenerHealth,
+    backoff: Duration,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -10,19 +29,205 @@ pub struct NewTaskPayload {
     pub run_at: chrono::DateTime<chrono::Utc>,
 }
 
+#[derive(Debug)]
+pub enum TaskWakeup {
+    Notified(NewTaskPayload),
+    /// A task finished, so a concurrency limit might have room for another one
+    Finished,
+    /// The listener (re)connected. Notifications sent while it was
+    /// disconnected are lost, so the caller should look for tasks.
+    Connected,
+}
+
 impl TaskListener {
-    pub async fn new(db: &sqlx::PgPool) -> Result<Self, sqlx::Error> {
-        let mut listener = sqlx::postgres::PgListener::connect_with(&db).await?;
-        listener.listen(constants::NEW_TASK_QUEUE).await?;
+    /// Doesn't connect until [`TaskListener::connect`] or [`TaskListener::take`] is called,
+    /// so a database that is down doesn't stop us from starting.
+    pub fn new(db: &PgPool, health: ListenerHealth) -> Self {
+        Self {
+            db: db.clone(),
+            listener: None,
+            health,
+            backoff: RECONNECT_BACKOFF.0,
+        }
+    }
+
+    /// Tries to connect right away, so the health check is accurate from the start
+    /// instead of once we first run out of tasks. If it fails, [`TaskListener::take`]
+    /// keeps trying with backoff.
+    pub async fn connect(&mut self) {
+        if let Err(err) = self.try_connect().await {
+            tracing::error!("can't listen to new tasks: {err}");
+            self.health.disconnected(err.to_string());
+        }
+    }
+
+    async fn try_connect(&mut self) -> Result<(), sqlx::Error> {
+        let mut listener = listen(&self.db, constants::NEW_TASK_QUEUE).await?;
+        let finished_channel = channel(&self.db, constants::FINISHED_TASK_QUEUE).await?;
+        listener.listen(&finished_channel).await?;
+
+        self.listener = Some((listener, finished_channel));
+        self.backoff = RECONNECT_BACKOFF.0;
+        self.health.connected();
+        Ok(())
+    }
+
+    pub async fn take(&mut self) -> TaskWakeup {
+        loop {
+            let Some((listener, finished_channel)) = &mut self.listener else {
+                match self.try_connect().await {
+                    Ok(()) => return TaskWakeup::Connected,
+                    Err(err) => {
+                        tracing::error!(
+                            "can't listen to new tasks, retrying in {:?}: {err}",
+                            self.backoff
+                        );
+                        self.health.disconnected(err.to_string());
+                        tokio::time::sleep(self.backoff).await;
+                        self.backoff = (self.backoff * 2).min(RECONNECT_BACKOFF.1);
+                        continue;
+                    }
+                }
+            };
+
+            // `try_recv` tells us when the connection is lost, where `recv` silently reconnects
+            match listener.try_recv().await {
+                Ok(Some(notification)) if notification.channel() == finished_channel => {
+                    return TaskWakeup::Finished;
+                }
+                Ok(Some(notification)) => {
+                    if let Ok(v) = serde_json::from_str(notification.payload()) {
+                        return TaskWakeup::Notified(v);
+                    }
+                }
+                Ok(None) => {
+                    tracing::warn!("lost the new tasks listener connection");
+                    self.health.disconnected("connection lost".to_string());
+                    self.listener = None;
+                }
+                Err(err) => {
+                    tracing::warn!("new tasks listener failed: {err}");
+                    self.health.disconnected(err.to_string());
+                    self.listener = None;
+                }
+            }
+        }
+    }
+}
+
+/// Shared view of whether a [`TaskListener`] is connected, for health checks
+#[derive(Debug, Clone, Default)]
+pub struct ListenerHealth(Arc<Mutex<ListenerStatus>>);
+
+#[derive(Debug, Clone, Default, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenerStatus {
+    pub connected: bool,
+    /// When the listener last connected or disconnected
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Why the listener last disconnected
+    pub last_error: Option<String>,
+    /// How many times the listener reconnected after the first connection
+    pub reconnects: u64,
+    #[serde(skip)]
+    #[schemars(skip)]
+    has_connected: bool,
+}
+
+impl ListenerHealth {
+    pub fn status(&self) -> ListenerStatus {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn connected(&self) {
+        let mut status = self.0.lock().unwrap();
+        if status.has_connected {
+            status.reconnects += 1;
+        }
+        status.has_connected = true;
+        status.connected = true;
+        status.since = Some(chrono::Utc::now());
+    }
+
+    fn disconnected(&self, error: String) {
+        let mut status = self.0.lock().unwrap();
+        if status.connected || status.since.is_none() {
+            status.since = Some(chrono::Utc::now());
+        }
+        status.connected = false;
+        status.last_error = Some(error);
+    }
+}
+
+pub struct FinishedTaskListener {
+    listener: PgListener,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct FinishedTaskPayload {
+    pub id: i64,
+}
+
+impl FinishedTaskListener {
+    pub async fn new(db: &PgPool) -> Result<Self, sqlx::Error> {
+        let listener = listen(db, constants::FINISHED_TASK_QUEUE).await?;
+        Ok(Self { listener })
+    }
+
+    /// Returns `None` when the connection was lost, so tasks that finished meanwhile
+    /// were missed and should be looked up again.
+    pub async fn take(&mut self) -> Result<Option<FinishedTaskPayload>, sqlx::Error> {
+        loop {
+            let Some(notification) = self.listener.try_recv().await? else {
+                return Ok(None);
+            };
+            if let Ok(v) = serde_json::from_str(notification.payload()) {
+                return Ok(Some(v));
+            }
+        }
+    }
+}
+
+/// Listens to requests to cancel running tasks.
+/// Every worker gets them, and ignores the tasks it isn't running.
+pub struct CancelTaskListener {
+    listener: PgListener,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct CancelTaskPayload {
+    pub id: i64,
+}
+
+impl CancelTaskListener {
+    pub async fn new(db: &PgPool) -> Result<Self, sqlx::Error> {
+        let listener = listen(db, constants::CANCEL_TASK_QUEUE).await?;
         Ok(Self { listener })
     }
 
-    pub async fn take(&mut self) -> Result<NewTaskPayload, sqlx::Error> {
+    /// Returns `None` when the connection was lost, so requests sent meanwhile
+    /// were missed. They can be found with [`crate::cancel_requested_tasks`].
+    pub async fn take(&mut self) -> Result<Option<CancelTaskPayload>, sqlx::Error> {
         loop {
-            let notification = self.listener.recv().await?;
+            let Some(notification) = self.listener.try_recv().await? else {
+                return Ok(None);
+            };
             if let Ok(v) = serde_json::from_str(notification.payload()) {
-                return Ok(v);
+                return Ok(Some(v));
             }
         }
     }
 }
+
+async fn listen(db: &PgPool, queue: &str) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(db).await?;
+    listener.listen(&channel(db, queue).await?).await?;
+    Ok(listener)
+}
+
+/// The channel name of a queue, namespaced by the schema the pool is using
+async fn channel(db: &PgPool, queue: &str) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar!("SELECT pointguard_channel($1) as \"channel!\"", queue)
+        .fetch_one(db)
+        .await
+}
@@ -1,6 +1,15 @@
 #[derive(Debug, serde::Serialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct InvokedTaskPayload<'a> {
+    /// The ID of the task
+    pub task_id: i64,
+    /// The name of the task, unique for its job and endpoint
+    pub name: &'a str,
+    /// Identifies this attempt. Retries of the task are new attempts.
+    pub attempt_id: &'a str,
+    /// Authenticates reports on this attempt, like `POST /api/v1/tasks/:id/complete`,
+    /// sent as `Authorization: Bearer <attemptToken>`
+    pub attempt_token: &'a str,
     /// The job name to invoke
     pub job_name: &'a str,
     /// The input data of the task
@@ -11,6 +20,24 @@ pub struct InvokedTaskPayload<'a> {
     pub max_retries: i32,
     /// The time when this task was enqueued at
     pub created_at: &'a chrono::DateTime<chrono::Utc>,
+    /// When the task was due to run. Earlier than `startedAt` when the workers were busy.
+    pub scheduled_for: &'a chrono::DateTime<chrono::Utc>,
+    /// When this attempt started
+    pub started_at: &'a chrono::DateTime<chrono::Utc>,
+}
+
+/// Sent to a task's cancel endpoint when it is cancelled while running
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelledTaskPayload<'a> {
+    /// The ID of the cancelled task
+    pub task_id: i64,
+    /// The job name that was invoked
+    pub job_name: &'a str,
+    /// The input data of the task
+    pub input: &'a serde_json::Value,
+    /// The amount of times we retried this task
+    pub retry_count: i32,
 }
 
 const fn bool_true() -> bool {
@@ -22,6 +49,24 @@ const fn bool_true() -> bool {
 pub enum InvokedTaskResponse {
     /// A successful invocation
     Success {},
+    /// The endpoint will finish the task on its own, and report back with
+    /// `POST /api/v1/tasks/:id/complete` or `POST /api/v1/tasks/:id/fail`
+    #[serde(rename_all = "camelCase")]
+    Accepted {
+        /// How long until the task is retried, unless it is reported on
+        /// or extended with `POST /api/v1/tasks/:id/heartbeat`
+        lease_seconds: u64,
+    },
+    /// Not yet: run the task again later, without counting it as a retry.
+    /// The number of times a task can be rescheduled is capped by the server.
+    #[serde(rename_all = "camelCase")]
+    Reschedule {
+        #[serde(flatten)]
+        when: RescheduleAt,
+        /// Replaces the task's input for the next invocation
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data: Option<serde_json::Value>,
+    },
     /// A failed invocation
     Failure {
         /// The reason why it failed
@@ -32,6 +77,22 @@ pub enum InvokedTaskResponse {
     },
 }
 
+/// When a rescheduled task runs again
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+pub enum RescheduleAt {
+    #[serde(rename_all = "camelCase")]
+    At {
+        /// When to run the task again
+        run_at: chrono::DateTime<chrono::Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    After {
+        /// How long from now to run the task again
+        delay_seconds: u64,
+    },
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase", tag = "type")]
 pub enum Event {
@@ -43,4 +104,60 @@ pub enum Event {
     TaskFailed,
     /// A task finished
     TaskFinished,
+    /// A running task was cancelled
+    TaskCancelled,
+    /// A task was rescheduled by its endpoint
+    TaskRescheduled,
+    /// A running task reported its progress
+    #[serde(rename_all = "camelCase")]
+    TaskProgress {
+        id: i64,
+        percent: Option<f32>,
+        message: Option<String>,
+    },
+    /// How many tasks a worker is running, out of how many it can run at once
+    WorkerUtilization { running: usize, concurrency: usize },
+}
+
+/// Headers sent with every invocation
+pub mod headers {
+    /// When pointguard stops waiting for the response, as an RFC 3339 timestamp
+    pub const DEADLINE: &str = "x-pointguard-deadline";
+    /// The `taskId` of the payload
+    pub const TASK_ID: &str = "x-pointguard-task-id";
+    /// The `name` of the payload
+    pub const TASK_NAME: &str = "x-pointguard-task-name";
+    /// The `attemptId` of the payload
+    pub const ATTEMPT_ID: &str = "x-pointguard-attempt-id";
+    /// The `scheduledFor` of the payload, as an RFC 3339 timestamp
+    pub const SCHEDULED_FOR: &str = "x-pointguard-scheduled-for";
+    /// The `startedAt` of the payload, as an RFC 3339 timestamp
+    pub const STARTED_AT: &str = "x-pointguard-started-at";
+    /// The task ID, which stays the same across retries. Signed along with the timestamp and the body.
+    pub const WEBHOOK_ID: &str = "webhook-id";
+    /// When the invocation was signed, in seconds since the Unix epoch
+    pub const WEBHOOK_TIMESTAMP: &str = "webhook-timestamp";
+    /// Space separated `v1,<base64 signature>` entries, one per signing secret
+    pub const WEBHOOK_SIGNATURE: &str = "webhook-signature";
+
+    /// Headers a task can't be enqueued with: hop-by-hop headers, the ones that describe
+    /// the request body, and the ones pointguard sets
+    pub fn is_reserved(name: &str) -> bool {
+        const HOP_BY_HOP: [&str; 8] = [
+            "connection",
+            "keep-alive",
+            "proxy-authenticate",
+            "proxy-authorization",
+            "te",
+            "trailer",
+            "transfer-encoding",
+            "upgrade",
+        ];
+        let name = name.to_ascii_lowercase();
+        HOP_BY_HOP.contains(&name.as_str())
+            || name == "host"
+            || name.starts_with("content-")
+            || name.starts_with("webhook-")
+            || name.starts_with("x-pointguard-")
+    }
 }
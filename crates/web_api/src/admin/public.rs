@@ -33,10 +33,8 @@ impl<ReqBody> Service<http::Request<ReqBody>> for ServePublic {
         let pathname = pathname.strip_prefix("/").unwrap_or(pathname);
         let mut data = Public::get(pathname);
 
-        if let None = data {
-            if !pathname.starts_with("assets/") {
-                data = Public::get("index.html");
-            }
+        if data.is_none() && !pathname.starts_with("assets/") {
+            data = Public::get("index.html");
         }
 
         let resp = match data {
@@ -50,6 +48,6 @@ impl<ReqBody> Service<http::Request<ReqBody>> for ServePublic {
 
         let fut = async { Ok(resp) };
 
-        return Box::pin(fut);
+        Box::pin(fut)
     }
 }
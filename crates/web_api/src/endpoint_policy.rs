@@ -0,0 +1,329 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// Which endpoints tasks can be sent to. Checked when tasks are enqueued
+/// and again when they are invoked, since DNS records can change in between.
+#[derive(Debug, Clone, Default)]
+pub struct EndpointPolicy {
+    /// When empty, any endpoint is allowed, as long as it's on a public address
+    pub allowed: Vec<EndpointPattern>,
+    /// Allows endpoints on loopback, private and link-local addresses
+    pub allow_private_networks: bool,
+}
+
+/// Matches endpoints like `https://*.example.com:8443`. The scheme and port are optional,
+/// and `*` in the host matches anything.
+#[derive(Debug, Clone)]
+pub struct EndpointPattern {
+    scheme: Option<String>,
+    host: String,
+    port: Option<u16>,
+}
+
+impl FromStr for EndpointPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let (scheme, authority) = match pattern.split_once("://") {
+            Some((scheme, authority)) => (Some(scheme.to_lowercase()), authority),
+            None => (None, pattern),
+        };
+        let authority = authority.trim_end_matches('/');
+
+        // IPv6 hosts are bracketed, like `[::1]:8080`
+        let port_separator = match authority.rfind(']') {
+            Some(end) => authority[end..].find(':').map(|i| end + i),
+            None => authority.rfind(':'),
+        };
+        let (host, port) = match port_separator {
+            Some(i) => {
+                let port = authority[i + 1..].parse::<u16>().map_err(|err| {
+                    format!("invalid port in endpoint pattern {pattern:?}: {err}")
+                })?;
+                (&authority[..i], Some(port))
+            }
+            None => (authority, None),
+        };
+
+        if host.is_empty() || host.contains('/') {
+            return Err(format!(
+                "endpoint patterns look like [scheme://]host[:port], got {pattern:?}"
+            ));
+        }
+
+        Ok(Self {
+            scheme,
+            host: host.to_lowercase(),
+            port,
+        })
+    }
+}
+
+impl EndpointPattern {
+    fn matches(&self, url: &url::Url) -> bool {
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        self.scheme.as_deref().is_none_or(|s| s == url.scheme())
+            && self
+                .port
+                .is_none_or(|p| Some(p) == url.port_or_known_default())
+            && glob_matches(&self.host, &host)
+    }
+}
+
+impl EndpointPolicy {
+    /// The checks that don't need DNS: the scheme, the allowlist and IP address hosts
+    pub fn allows(&self, url: &url::Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("{} endpoints are not supported", url.scheme()));
+        }
+
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|p| p.matches(url)) {
+            return Err(format!("{url} is not in the allowed endpoints"));
+        }
+
+        let ip = match url.host() {
+            Some(url::Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+            Some(url::Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+            Some(url::Host::Domain(_)) => None,
+            None => return Err(format!("{url} has no host")),
+        };
+        match ip {
+            Some(ip) if !self.allows_address(ip) => Err(format!("{url} is on a private address")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Like [`EndpointPolicy::allows`], and also makes sure the host
+    /// doesn't resolve to a private address
+    pub async fn check(&self, url: &url::Url) -> Result<(), String> {
+        self.allows(url)?;
+
+        let (Some(url::Host::Domain(domain)), Some(port)) =
+            (url.host(), url.port_or_known_default())
+        else {
+            return Ok(());
+        };
+        if self.allow_private_networks {
+            return Ok(());
+        }
+
+        // hosts that don't resolve can't reach anything, the invocation fails on its own
+        let Ok(addresses) = tokio::net::lookup_host((domain, port)).await else {
+            return Ok(());
+        };
+        for address in addresses {
+            if !self.allows_address(address.ip()) {
+                return Err(format!("{domain} resolves to a private address"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn allows_address(&self, ip: IpAddr) -> bool {
+        self.allow_private_networks || is_public(ip)
+    }
+}
+
+/// Whether the address is reachable on the internet, as opposed to loopback,
+/// private, link-local (like cloud metadata services) and other reserved ranges
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network", carrier-grade NAT, IETF protocol assignments, benchmarking and reserved
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_public_v4(ip);
+    }
+    let segments = ip.segments();
+    // NAT64 addresses embed an IPv4 address
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // unique local, link-local and documentation
+        || segments[0] & 0xfe00 == 0xfc00
+        || segments[0] & 0xffc0 == 0xfe80
+        || segments[..2] == [0x2001, 0xdb8])
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of characters
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let Some(text) = text.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=text.len())
+                .filter(|&i| text.is_char_boundary(i))
+                .any(|i| glob_matches(rest, &text[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allows(policy: &EndpointPolicy, url: &str) -> bool {
+        policy.allows(&url.parse().unwrap()).is_ok()
+    }
+
+    fn allowing(patterns: &[&str]) -> EndpointPolicy {
+        EndpointPolicy {
+            allowed: patterns.iter().map(|p| p.parse().unwrap()).collect(),
+            allow_private_networks: false,
+        }
+    }
+
+    #[test]
+    fn rejects_private_addresses() {
+        let policy = EndpointPolicy::default();
+        for url in [
+            "http://127.0.0.1/",
+            "http://[::1]/",
+            "http://0.0.0.0/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1/",
+            "http://192.168.1.1/",
+            "http://[fe80::1]/",
+            "http://[fd00::1]/",
+            // IPv4-mapped and NAT64 addresses are checked as the IPv4 address they embed
+            "http://[::ffff:127.0.0.1]/",
+            "http://[::ffff:169.254.169.254]/",
+            "http://[64:ff9b::a9fe:a9fe]/",
+            // the URL parser reads these as 127.0.0.1 and 0.0.0.0
+            "http://2130706433/",
+            "http://0x7f.1/",
+            "http://0/",
+        ] {
+            assert!(!allows(&policy, url), "{url} should be rejected");
+        }
+    }
+
+    #[test]
+    fn allows_public_addresses() {
+        let policy = EndpointPolicy::default();
+        for url in [
+            "https://example.com/",
+            "http://93.184.216.34/",
+            "http://[2606:2800:220:1:248:1893:25c8:1946]/",
+            "http://[::ffff:93.184.216.34]/",
+        ] {
+            assert!(allows(&policy, url), "{url} should be allowed");
+        }
+    }
+
+    #[test]
+    fn allows_private_addresses_when_asked() {
+        let policy = EndpointPolicy {
+            allow_private_networks: true,
+            ..Default::default()
+        };
+        assert!(allows(&policy, "http://127.0.0.1:8080/"));
+        assert!(allows(&policy, "http://[::1]/"));
+        assert!(allows(&policy, "http://169.254.169.254/"));
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        let policy = EndpointPolicy::default();
+        assert!(!allows(&policy, "ftp://example.com/"));
+        assert!(!allows(&policy, "file:///etc/passwd"));
+    }
+
+    #[test]
+    fn wildcards_match_whole_subdomains_only() {
+        let policy = allowing(&["*.example.com"]);
+        assert!(allows(&policy, "https://api.example.com/"));
+        assert!(allows(&policy, "https://a.b.example.com/"));
+        assert!(allows(&policy, "https://API.Example.COM/"));
+        assert!(!allows(&policy, "https://example.com/"));
+        assert!(!allows(&policy, "https://evilexample.com/"));
+        assert!(!allows(&policy, "https://example.com.evil.net/"));
+        assert!(!allows(&policy, "https://api.example.com.evil.net/"));
+    }
+
+    #[test]
+    fn hosts_without_wildcards_match_exactly() {
+        let policy = allowing(&["example.com"]);
+        assert!(allows(&policy, "https://example.com/tasks"));
+        assert!(!allows(&policy, "https://api.example.com/"));
+        assert!(!allows(&policy, "https://example.com.evil.net/"));
+        assert!(!allows(&policy, "https://notexample.com/"));
+    }
+
+    #[test]
+    fn wildcards_at_the_end_and_middle() {
+        let policy = allowing(&["api.*", "eu-*.example.com"]);
+        assert!(allows(&policy, "https://api.example.com/"));
+        assert!(!allows(&policy, "https://myapi.example.com/"));
+        assert!(allows(&policy, "https://eu-west.example.com/"));
+        assert!(allows(&policy, "https://eu-.example.com/"));
+        assert!(!allows(&policy, "https://us-west.example.com/"));
+        assert!(!allows(&policy, "https://eu-west.example.com.evil.net/"));
+    }
+
+    #[test]
+    fn patterns_restrict_the_scheme_and_port() {
+        let policy = allowing(&["https://example.com:8443"]);
+        assert!(allows(&policy, "https://example.com:8443/"));
+        assert!(!allows(&policy, "http://example.com:8443/"));
+        assert!(!allows(&policy, "https://example.com/"));
+
+        let policy = allowing(&["https://example.com"]);
+        assert!(allows(&policy, "https://example.com/"));
+        assert!(allows(&policy, "https://example.com:8443/"));
+
+        let policy = allowing(&["example.com:443"]);
+        assert!(allows(&policy, "https://example.com/"));
+        assert!(!allows(&policy, "http://example.com/"));
+    }
+
+    #[test]
+    fn allowed_patterns_dont_allow_private_addresses() {
+        let policy = allowing(&["127.0.0.1", "[::1]:8080"]);
+        assert!(!allows(&policy, "http://127.0.0.1/"));
+        assert!(!allows(&policy, "http://[::1]:8080/"));
+    }
+
+    #[test]
+    fn parses_ipv6_patterns() {
+        let pattern: EndpointPattern = "http://[::1]:8080/".parse().unwrap();
+        assert_eq!(pattern.host, "[::1]");
+        assert_eq!(pattern.port, Some(8080));
+        let pattern: EndpointPattern = "[::1]".parse().unwrap();
+        assert_eq!(pattern.port, None);
+        assert!("example.com:http".parse::<EndpointPattern>().is_err());
+        assert!("https://example.com/path"
+            .parse::<EndpointPattern>()
+            .is_err());
+        assert!("https://".parse::<EndpointPattern>().is_err());
+    }
+}
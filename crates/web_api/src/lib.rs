@@ -1,6 +1,9 @@
 mod admin;
+mod endpoint_policy;
 pub mod openapi;
+mod read_pool;
 mod router;
+mod task_waiter;
 
 use axum::Extension;
 use db::postgres::PgPool;
@@ -8,19 +11,51 @@ use flume::{Receiver, Sender};
 use futures::Future;
 use pointguard_engine_postgres as db;
 use pointguard_types::Event;
+use read_pool::ReadPool;
+use std::sync::Arc;
+use task_waiter::TaskWaiter;
 
-pub use router::api_router;
+pub use endpoint_policy::{EndpointPattern, EndpointPolicy};
+pub use router::{api_router, parse_invocation_timeout};
 
 #[derive(Clone)]
 pub struct AppState {
     db: db::postgres::PgPool,
+    /// For listing queries that can be served by a read replica
+    read_db: ReadPool,
+    task_listener: db::ListenerHealth,
+    waiter: TaskWaiter,
+    /// Which endpoints tasks can be enqueued with
+    endpoint_policy: Arc<EndpointPolicy>,
+    /// Encrypts the headers tasks are enqueued with, the first one is used
+    header_keys: Arc<[db::HeaderKey]>,
+}
+
+pub type OnBind = Box<dyn FnOnce(&str, u16) + Send + Sync>;
+
+/// A namespace served under `/t/:name`, backed by its own pool
+pub struct Tenant {
+    pub name: String,
+    pub pool: PgPool,
+    pub read_pool: Option<PgPool>,
+    pub task_listener: db::ListenerHealth,
+    pub events: (Sender<Event>, Receiver<Event>),
 }
 
 pub struct Server {
     pub pool: PgPool,
+    /// A read replica for the listing endpoints, if any
+    pub read_pool: Option<PgPool>,
+    /// Reported by the health endpoint
+    pub task_listener: db::ListenerHealth,
+    pub tenants: Vec<Tenant>,
+    /// Shared by every tenant
+    pub endpoint_policy: Arc<EndpointPolicy>,
+    /// Shared by every tenant
+    pub header_keys: Arc<[db::HeaderKey]>,
     pub host: String,
     pub port: u16,
-    pub on_bind: Box<dyn FnOnce(&str, u16) + Send + Sync>,
+    pub on_bind: OnBind,
 }
 
 impl Server {
@@ -32,11 +67,34 @@ impl Server {
         let mut api = openapi::new();
 
         let mut app = api_router(&mut api)
-            .with_state(AppState { db: self.pool })
+            .with_state(AppState {
+                waiter: TaskWaiter::spawn(self.pool.clone()),
+                read_db: ReadPool::new(self.pool.clone(), self.read_pool),
+                task_listener: self.task_listener,
+                endpoint_policy: self.endpoint_policy.clone(),
+                header_keys: self.header_keys.clone(),
+                db: self.pool,
+            })
             .layer(Extension(api))
             .layer(Extension(events_tx))
             .layer(Extension(events_rx));
 
+        for tenant in self.tenants {
+            let (events_tx, events_rx) = tenant.events;
+            let tenant_app = axum::Router::from(router::v1_routes())
+                .with_state(AppState {
+                    waiter: TaskWaiter::spawn(tenant.pool.clone()),
+                    read_db: ReadPool::new(tenant.pool.clone(), tenant.read_pool),
+                    task_listener: tenant.task_listener,
+                    endpoint_policy: self.endpoint_policy.clone(),
+                    header_keys: self.header_keys.clone(),
+                    db: tenant.pool,
+                })
+                .layer(Extension(events_tx))
+                .layer(Extension(events_rx));
+            app = app.nest(&format!("/t/{}", tenant.name), tenant_app);
+        }
+
         #[cfg(debug_assertions)]
         {
             let reloader = tower_livereload::LiveReloadLayer::new();
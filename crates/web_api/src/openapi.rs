@@ -1,6 +1,9 @@
-use aide::openapi::{Info, OpenApi, Operation, PathItem};
+use aide::openapi::{
+    HeaderStyle, Info, OpenApi, Operation, Parameter, ParameterData, ParameterSchemaOrContent,
+    PathItem, ReferenceOr, SchemaObject,
+};
 use axum::Json;
-use pointguard_types::{InvokedTaskPayload, InvokedTaskResponse};
+use pointguard_types::{headers, InvokedTaskPayload, InvokedTaskResponse};
 use schemars::JsonSchema;
 
 pub fn new() -> OpenApi {
@@ -16,12 +19,45 @@ pub fn new() -> OpenApi {
 
     let mut operation = Operation::default();
     let _ = aide::transform::TransformOperation::new(&mut operation)
+        .description(EXECUTE_TASK_DESCRIPTION)
         .input::<Json<InvokedTaskPayload>>()
         .response::<200, Json<InvokedTaskResponse>>();
+    operation.parameters.extend([
+        header::<String>(
+            headers::DEADLINE,
+            "When pointguard stops waiting for the response, as an RFC 3339 timestamp. A timed out invocation is retried.",
+        ),
+        header::<i64>(headers::TASK_ID, "The `taskId` of the payload."),
+        header::<String>(
+            headers::TASK_NAME,
+            "The `name` of the payload. Left out when the name can't be sent as a header.",
+        ),
+        header::<String>(headers::ATTEMPT_ID, "The `attemptId` of the payload."),
+        header::<String>(
+            headers::SCHEDULED_FOR,
+            "The `scheduledFor` of the payload, as an RFC 3339 timestamp.",
+        ),
+        header::<String>(
+            headers::STARTED_AT,
+            "The `startedAt` of the payload, as an RFC 3339 timestamp.",
+        ),
+        header::<String>(
+            headers::WEBHOOK_ID,
+            "The `taskId` of the payload, the same for every attempt so retries can be deduplicated. Only sent when the server has signing secrets.",
+        ),
+        header::<i64>(
+            headers::WEBHOOK_TIMESTAMP,
+            "When the invocation was signed, in seconds since the Unix epoch. Only sent when the server has signing secrets.",
+        ),
+        header::<String>(
+            headers::WEBHOOK_SIGNATURE,
+            "Space separated `v1,<base64 signature>` entries, one per signing secret. Only sent when the server has signing secrets.",
+        ),
+    ]);
 
     api.webhooks.insert(
         "executeTask".to_string(),
-        aide::openapi::ReferenceOr::Item(PathItem {
+        ReferenceOr::Item(PathItem {
             post: Some(operation),
             ..Default::default()
         }),
@@ -30,11 +66,47 @@ pub fn new() -> OpenApi {
     api
 }
 
+/// How endpoints verify invocations, following Standard Webhooks
+const EXECUTE_TASK_DESCRIPTION: &str = "Invokes a task. \
+When the server is started with `--signing-secret`, requests are signed following \
+[Standard Webhooks](https://www.standardwebhooks.com), so any of its libraries can verify them. \
+To verify by hand:
+
+1. Reject the request if `webhook-timestamp` is more than a few minutes away from now.
+2. Compute the HMAC-SHA256 of `{webhook-id}.{webhook-timestamp}.{raw body}`, keyed with \
+the base64-decoded part of the secret after `whsec_`.
+3. Accept the request if the base64 encoded result matches any of the `v1,` entries \
+of `webhook-signature`, comparing in constant time.
+
+Every configured secret signs the request, so a secret can be rotated by adding the new one, \
+updating the endpoints, and then removing the old one.";
+
+fn header<T: JsonSchema>(name: &str, description: &str) -> ReferenceOr<Parameter> {
+    ReferenceOr::Item(Parameter::Header {
+        parameter_data: ParameterData {
+            name: name.to_string(),
+            description: Some(description.to_string()),
+            required: false,
+            deprecated: None,
+            format: ParameterSchemaOrContent::Schema(SchemaObject {
+                json_schema: schemars::schema::Schema::Object(schemars::schema_for!(T).schema),
+                external_docs: None,
+                example: None,
+            }),
+            example: None,
+            examples: Default::default(),
+            explode: None,
+            extensions: Default::default(),
+        },
+        style: HeaderStyle::Simple,
+    })
+}
+
 fn register_component<T: JsonSchema>(api: &mut OpenApi, name: &str) {
     let mut components = api.components.take().unwrap_or_default();
     components.schemas.insert(
         name.to_string(),
-        aide::openapi::SchemaObject {
+        SchemaObject {
             json_schema: schemars::schema::Schema::Object(schemars::schema_for!(T).schema),
             external_docs: None,
             example: None,
@@ -0,0 +1,63 @@
+use db::postgres::PgPool;
+use futures::Future;
+use pointguard_engine_postgres as db;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long we stop trying the replica after it failed to connect
+const REPLICA_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Routes read-only listing queries to a read replica when one is configured,
+/// falling back to the primary when the replica can't be reached.
+#[derive(Clone)]
+pub(crate) struct ReadPool {
+    primary: PgPool,
+    replica: Option<Replica>,
+}
+
+#[derive(Clone)]
+struct Replica {
+    pool: PgPool,
+    unavailable_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ReadPool {
+    pub(crate) fn new(primary: PgPool, replica: Option<PgPool>) -> Self {
+        Self {
+            primary,
+            replica: replica.map(|pool| Replica {
+                pool,
+                unavailable_until: Default::default(),
+            }),
+        }
+    }
+
+    pub(crate) async fn run<T, F, Fut>(&self, query: F) -> Result<T, db::Error>
+    where
+        F: Fn(PgPool) -> Fut,
+        Fut: Future<Output = Result<T, db::Error>>,
+    {
+        if let Some(replica) = self.replica.as_ref().filter(|r| r.is_available()) {
+            match query(replica.pool.clone()).await {
+                Err(err) if db::is_connection_error(&err) => {
+                    tracing::warn!("read replica unavailable, falling back to primary: {err}");
+                    replica.mark_unavailable();
+                }
+                result => return result,
+            }
+        }
+
+        query(self.primary.clone()).await
+    }
+}
+
+impl Replica {
+    fn is_available(&self) -> bool {
+        let unavailable_until = self.unavailable_until.lock().unwrap();
+        unavailable_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn mark_unavailable(&self) {
+        *self.unavailable_until.lock().unwrap() = Some(Instant::now() + REPLICA_BACKOFF);
+    }
+}
@@ -1,38 +1,49 @@
 use crate::admin::admin_routes;
-use crate::AppState;
+use crate::{task_waiter::Finished, AppState};
 use aide::{
     axum::{
-        routing::{get, get_with, post},
+        routing::{get, get_with, post, post_with, put_with},
         ApiRouter, IntoApiResponse,
     },
     openapi::OpenApi,
     redoc::Redoc,
 };
 use axum::{
-    extract::{Path, Query, State},
-    response::{IntoResponse, Redirect, Sse},
+    extract::{rejection::JsonRejection, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response, Sse},
     Extension, Json,
 };
 use db::PaginationCursor;
 use flume::{Receiver, Sender};
 use futures::StreamExt;
 use pointguard_engine_postgres as db;
-use pointguard_types::Event;
+use pointguard_types::{headers, Event};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
 
 async fn get_finished_tasks(
     State(state): State<AppState>,
     Query(query): Query<PaginationCursor>,
+    Query(range): Query<db::FinishedTasksRange>,
 ) -> impl IntoApiResponse {
-    let finished_tasks = db::finished_tasks(&state.db, &query)
+    let (query, range) = (&query, &range);
+    let finished_tasks = state
+        .read_db
+        .run(|db| async move { db::finished_tasks(&db, query, range).await })
         .await
         .expect("finished tasks");
     Json(finished_tasks)
 }
 
 async fn get_enqueued_tasks(State(state): State<AppState>) -> impl IntoApiResponse {
-    let enqueued_tasks = db::enqueued_tasks(&state.db).await.expect("enqueued tasks");
+    let enqueued_tasks = state
+        .read_db
+        .run(|db| async move { db::enqueued_tasks(&db).await })
+        .await
+        .expect("enqueued tasks");
     Json(enqueued_tasks)
 }
 
@@ -40,29 +51,332 @@ async fn serve_api(Extension(api): Extension<OpenApi>) -> impl IntoApiResponse {
     Json(api)
 }
 
+/// The task a `/api/v1/tasks/:id/...` route is about
 #[derive(Debug, Deserialize, Serialize, JsonSchema)]
-struct CancelTaskParams {
+struct TaskParams {
     id: i64,
 }
 
 async fn cancel_task(
     State(state): State<AppState>,
-    axum::extract::Path(path): axum::extract::Path<CancelTaskParams>,
+    axum::extract::Path(path): axum::extract::Path<TaskParams>,
 ) -> impl IntoApiResponse {
     let _task = db::cancel_task(&state.db, path.id)
         .await
         .expect("cancel task");
-    Redirect::to("/api/v1/tasks/enqueued")
+    Redirect::to("../enqueued")
 }
 
 async fn unshift_task(
     State(state): State<AppState>,
-    axum::extract::Path(path): axum::extract::Path<CancelTaskParams>,
+    axum::extract::Path(path): axum::extract::Path<TaskParams>,
 ) -> impl IntoApiResponse {
     let _task = db::unshift_job(&state.db, path.id)
         .await
         .expect("unshift task");
-    Redirect::to("/api/v1/tasks/enqueued")
+    Redirect::to("../enqueued")
+}
+
+async fn get_concurrency_limits(State(state): State<AppState>) -> impl IntoApiResponse {
+    let limits = db::concurrency_limits(&state.db)
+        .await
+        .expect("concurrency limits");
+    Json(limits)
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct ConcurrencyLimitParams {
+    scope: db::ConcurrencyLimitScope,
+    /// The job name or endpoint host
+    key: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct ConcurrencyLimitBody {
+    /// How many matching tasks can be running at once
+    max_concurrency: i32,
+}
+
+async fn set_concurrency_limit(
+    State(state): State<AppState>,
+    axum::extract::Path(path): axum::extract::Path<ConcurrencyLimitParams>,
+    Json(body): Json<ConcurrencyLimitBody>,
+) -> Response {
+    if body.max_concurrency < 1 {
+        return (StatusCode::BAD_REQUEST, "maxConcurrency must be at least 1").into_response();
+    }
+    db::set_concurrency_limit(&state.db, path.scope, &path.key, body.max_concurrency)
+        .await
+        .expect("set concurrency limit");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn remove_concurrency_limit(
+    State(state): State<AppState>,
+    axum::extract::Path(path): axum::extract::Path<ConcurrencyLimitParams>,
+) -> StatusCode {
+    let removed = db::remove_concurrency_limit(&state.db, path.scope, &path.key)
+        .await
+        .expect("remove concurrency limit");
+    if removed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Sent by endpoints as `Authorization: Bearer <attemptToken>`
+fn attempt_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// The task an endpoint is reporting on, by the attempt token it was invoked with
+async fn attempted_task(
+    state: &AppState,
+    id: i64,
+    headers: &HeaderMap,
+) -> Result<db::InflightTask, StatusCode> {
+    let attempt_token = attempt_token(headers).ok_or(StatusCode::UNAUTHORIZED)?;
+    db::take_attempted_task(&state.db, id, attempt_token)
+        .await
+        .expect("take attempted task")
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn complete_task(
+    Extension(event_tx): Extension<Sender<Event>>,
+    State(state): State<AppState>,
+    axum::extract::Path(path): axum::extract::Path<TaskParams>,
+    headers: HeaderMap,
+) -> StatusCode {
+    let task = match attempted_task(&state, path.id, &headers).await {
+        Ok(task) => task,
+        Err(status) => return status,
+    };
+    task.done(&state.db).await;
+    _ = event_tx.send_async(Event::TaskFinished).await;
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct FailTaskBody {
+    /// The reason why it failed
+    reason: String,
+    /// Whether or not this task is retriable. Defaults to true.
+    retriable: Option<bool>,
+}
+
+async fn fail_task(
+    Extension(event_tx): Extension<Sender<Event>>,
+    State(state): State<AppState>,
+    axum::extract::Path(path): axum::extract::Path<TaskParams>,
+    headers: HeaderMap,
+    Json(body): Json<FailTaskBody>,
+) -> StatusCode {
+    let task = match attempted_task(&state, path.id, &headers).await {
+        Ok(task) => task,
+        Err(status) => return status,
+    };
+    task.failed(
+        &state.db,
+        &body.reason,
+        body.retriable.unwrap_or(true),
+        None,
+    )
+    .await;
+    _ = event_tx.send_async(Event::TaskFailed).await;
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct HeartbeatBody {
+    /// How long from now until the task is retried, up to 7 days.
+    /// Defaults to the lease the task was accepted with.
+    lease_seconds: Option<u64>,
+}
+
+fn has_body(headers: &HeaderMap) -> bool {
+    let content_length = headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    content_length.is_some_and(|length| length > 0)
+        || headers.contains_key(http::header::TRANSFER_ENCODING)
+}
+
+async fn heartbeat_task(
+    State(state): State<AppState>,
+    axum::extract::Path(path): axum::extract::Path<TaskParams>,
+    headers: HeaderMap,
+    body: Result<Json<HeartbeatBody>, JsonRejection>,
+) -> Response {
+    let Some(attempt_token) = attempt_token(&headers) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    // the body is optional, but one we can't read shouldn't extend the lease by the old duration
+    let body = match body {
+        Ok(Json(body)) => Some(body),
+        Err(_) if !has_body(&headers) => None,
+        Err(rejection) => {
+            return (StatusCode::BAD_REQUEST, rejection.body_text()).into_response();
+        }
+    };
+    let lease = body
+        .and_then(|body| body.lease_seconds)
+        .map(Duration::from_secs);
+    if lease.is_some_and(|lease| lease > db::MAX_LEASE) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("leaseSeconds must be at most {}", db::MAX_LEASE.as_secs()),
+        )
+            .into_response();
+    }
+
+    let extended = db::extend_lease(&state.db, path.id, attempt_token, lease)
+        .await
+        .expect("extend lease");
+    if extended {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct ProgressBody {
+    /// How far along the task is, from 0 to 100
+    percent: Option<f32>,
+    /// What the task is doing
+    message: Option<String>,
+}
+
+async fn report_progress(
+    Extension(event_tx): Extension<Sender<Event>>,
+    State(state): State<AppState>,
+    axum::extract::Path(path): axum::extract::Path<TaskParams>,
+    headers: HeaderMap,
+    Json(body): Json<ProgressBody>,
+) -> Response {
+    let Some(attempt_token) = attempt_token(&headers) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    if body
+        .percent
+        .is_some_and(|percent| !(0.0..=100.0).contains(&percent))
+    {
+        return (StatusCode::BAD_REQUEST, "percent must be between 0 and 100").into_response();
+    }
+
+    let reported = db::report_progress(
+        &state.db,
+        path.id,
+        attempt_token,
+        body.percent,
+        body.message.as_deref(),
+    )
+    .await
+    .expect("report progress");
+    if !reported {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    _ = event_tx
+        .send_async(Event::TaskProgress {
+            id: path.id,
+            percent: body.percent,
+            message: body.message,
+        })
+        .await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn get_ongoing_tasks(State(state): State<AppState>) -> impl IntoApiResponse {
+    let ongoing_tasks = db::ongoing_tasks(&state.db).await.expect("ongoing tasks");
+    Json(ongoing_tasks)
+}
+
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_WAIT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct WaitForTaskQuery {
+    /// How long to wait for the task to finish, e.g. "30s" or "2m".
+    /// Defaults to 30 seconds and is capped at 5 minutes.
+    timeout: Option<String>,
+}
+
+async fn wait_for_task(
+    State(state): State<AppState>,
+    axum::extract::Path(path): axum::extract::Path<TaskParams>,
+    Query(query): Query<WaitForTaskQuery>,
+) -> Response {
+    let timeout = match query.timeout.as_deref().map(humantime::parse_duration) {
+        None => DEFAULT_WAIT_TIMEOUT,
+        Some(Ok(timeout)) => timeout.min(MAX_WAIT_TIMEOUT),
+        Some(Err(err)) => {
+            return (StatusCode::BAD_REQUEST, format!("invalid timeout: {err}")).into_response()
+        }
+    };
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    // subscribe before looking at the database so we can't miss the notification
+    let mut finished_rx = state.waiter.subscribe();
+    let mut was_enqueued = false;
+
+    loop {
+        // a task is deleted in the same transaction that records it as finished,
+        // so looking at `tasks` first means we can't miss it in between
+        let enqueued = db::is_enqueued(&state.db, path.id)
+            .await
+            .expect("is enqueued");
+        let finished = db::finished_task(&state.db, path.id)
+            .await
+            .expect("finished task");
+        if let Some(finished) = finished {
+            return Json(finished).into_response();
+        }
+
+        match (enqueued, was_enqueued) {
+            (true, _) => was_enqueued = true,
+            (false, false) => return StatusCode::NOT_FOUND.into_response(),
+            // it was removed without finishing, like when it's cancelled before it runs
+            (false, true) => return StatusCode::GONE.into_response(),
+        }
+
+        loop {
+            match tokio::time::timeout_at(deadline, finished_rx.recv()).await {
+                Err(_) | Ok(Err(RecvError::Closed)) => return StatusCode::ACCEPTED.into_response(),
+                Ok(Ok(Finished::Task(id))) if id == path.id => break,
+                Ok(Ok(Finished::Task(_))) => continue,
+                // we might have missed our notification, so look again
+                Ok(Ok(Finished::Missed)) | Ok(Err(RecvError::Lagged(_))) => break,
+            }
+        }
+    }
+}
+
+const MAX_INVOCATION_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An invocation timeout, from 1ms to 24h, for tasks, jobs and the server's default.
+/// Task timeouts are stored with millisecond precision.
+pub fn parse_invocation_timeout(timeout: &str) -> Result<Duration, String> {
+    let timeout =
+        humantime::parse_duration(timeout).map_err(|err| format!("invalid timeout: {err}"))?;
+    if timeout < Duration::from_millis(1) {
+        return Err("timeout must be at least 1ms".to_string());
+    }
+    if timeout > MAX_INVOCATION_TIMEOUT {
+        return Err(format!(
+            "timeout must be at most {}",
+            humantime::format_duration(MAX_INVOCATION_TIMEOUT)
+        ));
+    }
+    Ok(timeout)
 }
 
 #[tracing::instrument(skip_all, fields(%new_task.job_name))]
@@ -70,7 +384,46 @@ async fn post_tasks(
     Extension(event_tx): Extension<Sender<Event>>,
     State(state): State<AppState>,
     Json(new_task): Json<NewTaskBody>,
-) -> impl IntoApiResponse {
+) -> Response {
+    let timeout = match new_task.timeout.as_deref().map(parse_invocation_timeout) {
+        None => None,
+        Some(Ok(timeout)) => Some(timeout),
+        Some(Err(reason)) => return (StatusCode::BAD_REQUEST, reason).into_response(),
+    };
+
+    for (name, value) in &new_task.headers {
+        let valid = http::HeaderName::from_bytes(name.as_bytes()).is_ok()
+            && http::HeaderValue::from_str(value).is_ok();
+        if !valid {
+            return (StatusCode::BAD_REQUEST, format!("invalid header {name:?}")).into_response();
+        }
+        if headers::is_reserved(name) {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("header {name:?} is reserved"),
+            )
+                .into_response();
+        }
+    }
+    if !new_task.headers.is_empty() && state.header_keys.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "tasks can't have headers unless the server has a header encryption key",
+        )
+            .into_response();
+    }
+
+    let endpoints = std::iter::once(&new_task.endpoint).chain(&new_task.cancel_endpoint);
+    for endpoint in endpoints {
+        if let Err(reason) = state.endpoint_policy.check(endpoint).await {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("endpoint not allowed: {reason}"),
+            )
+                .into_response();
+        }
+    }
+
     let id = db::enqueue(
         &state.db,
         &db::NewTask {
@@ -80,6 +433,10 @@ async fn post_tasks(
             endpoint: new_task.endpoint.to_string(),
             name: new_task.name.unwrap_or_else(generate_nanoid),
             run_at: new_task.run_at,
+            cancel_endpoint: new_task.cancel_endpoint.map(String::from),
+            timeout,
+            headers: db::encrypt_headers(&state.header_keys, new_task.headers),
+            response_mode: new_task.response_mode,
         },
     )
     .await
@@ -90,7 +447,23 @@ async fn post_tasks(
         .await
         .expect("send task");
 
-    Json(id)
+    Json(id).into_response()
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct Health {
+    task_listener: db::ListenerStatus,
+}
+
+async fn health(State(state): State<AppState>) -> impl IntoApiResponse {
+    let task_listener = state.task_listener.status();
+    let status = if task_listener.connected {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(Health { task_listener }))
 }
 
 async fn events(Extension(event_rx): Extension<Receiver<Event>>) -> impl IntoApiResponse {
@@ -107,6 +480,14 @@ pub fn api_router(api: &mut OpenApi) -> axum::Router<AppState> {
         .route("/api", Redoc::new("/api/openapi.json").axum_route())
         .route("/api/openapi.json", get(serve_api))
         .nest("/", admin_routes())
+        .merge(v1_routes())
+        .finish_api_with(api, |api| api.default_response::<String>())
+}
+
+/// The routes that operate on a single database,
+/// so they can be mounted once per tenant.
+pub(crate) fn v1_routes() -> ApiRouter<AppState> {
+    ApiRouter::new()
         .api_route(
             "/api/v1/events",
             get_with(events, |r| {
@@ -117,12 +498,106 @@ pub fn api_router(api: &mut OpenApi) -> axum::Router<AppState> {
             }),
         )
         .api_route("/api/v1/version", get(stub))
-        .api_route("/api/v1/tasks", post(post_tasks))
-        .api_route("/api/v1/tasks/:id/cancel", post(cancel_task))
+        .api_route(
+            "/api/v1/health",
+            get_with(health, |r| {
+                r.summary("/api/v1/health")
+                    .description("whether this server can pick up new tasks as they are enqueued.")
+                    .response::<200, Json<Health>>()
+                    .response_with::<503, Json<Health>, _>(|r| {
+                        r.description("the task listener is disconnected from the database")
+                    })
+            }),
+        )
+        .api_route(
+            "/api/v1/tasks",
+            post_with(post_tasks, |r| {
+                r.summary("/api/v1/tasks")
+                    .description("enqueue a task. Responds with its ID, or with the ID of the existing task with the same job name, name and endpoint.")
+                    .response::<200, Json<i64>>()
+                    .response_with::<400, String, _>(|r| r.description("the timeout or a header is invalid or reserved, the server can't encrypt headers, or the endpoint is not allowed"))
+            }),
+        )
+        .api_route(
+            "/api/v1/tasks/:id/cancel",
+            post_with(cancel_task, |r| {
+                r.summary("/api/v1/tasks/:id/cancel")
+                    .description("cancel a task. An enqueued task is removed, and a running task is stopped and recorded as cancelled.")
+            }),
+        )
         .api_route("/api/v1/tasks/:id/unshift", post(unshift_task))
+        .api_route(
+            "/api/v1/tasks/:id/complete",
+            post_with(complete_task, |r| {
+                r.summary("/api/v1/tasks/:id/complete")
+                    .description("report that an accepted task succeeded. Authenticated with the attempt token the endpoint was invoked with, as `Authorization: Bearer <attemptToken>`.")
+                    .response_with::<204, (), _>(|r| r.description("the task is done"))
+                    .response_with::<401, (), _>(|r| r.description("the attempt token is missing"))
+                    .response_with::<404, (), _>(|r| r.description("there is no such attempt, or the task was cancelled"))
+            }),
+        )
+        .api_route(
+            "/api/v1/tasks/:id/fail",
+            post_with(fail_task, |r| {
+                r.summary("/api/v1/tasks/:id/fail")
+                    .description("report that an accepted task failed. It is retried if it is retriable and has retries left. Authenticated like `/complete`.")
+                    .response_with::<204, (), _>(|r| r.description("the failure was recorded"))
+                    .response_with::<401, (), _>(|r| r.description("the attempt token is missing"))
+                    .response_with::<404, (), _>(|r| r.description("there is no such attempt, or the task was cancelled"))
+            }),
+        )
+        .api_route(
+            "/api/v1/tasks/:id/progress",
+            post_with(report_progress, |r| {
+                r.summary("/api/v1/tasks/:id/progress")
+                    .description("report the progress of a running task. It shows up in the ongoing tasks and the events stream. Authenticated like `/complete`.")
+                    .response_with::<204, (), _>(|r| r.description("the progress was stored"))
+                    .response_with::<400, String, _>(|r| r.description("percent is not between 0 and 100"))
+                    .response_with::<401, (), _>(|r| r.description("the attempt token is missing"))
+                    .response_with::<404, (), _>(|r| r.description("there is no such attempt"))
+            }),
+        )
+        .api_route(
+            "/api/v1/tasks/:id/heartbeat",
+            post_with(heartbeat_task, |r| {
+                r.summary("/api/v1/tasks/:id/heartbeat")
+                    .description("extend the lease of an accepted task that is still being worked on. Authenticated like `/complete`.")
+                    .response_with::<204, (), _>(|r| r.description("the lease was extended"))
+                    .response_with::<400, String, _>(|r| r.description("the body is invalid, or leaseSeconds is more than 7 days"))
+                    .response_with::<401, (), _>(|r| r.description("the attempt token is missing"))
+                    .response_with::<404, (), _>(|r| r.description("there is no such accepted attempt, or the task was cancelled"))
+            }),
+        )
+        .api_route(
+            "/api/v1/tasks/:id/wait",
+            get_with(wait_for_task, |r| {
+                r.summary("/api/v1/tasks/:id/wait")
+                    .description("wait for a task to reach a terminal state. Responds with the finished task as soon as it is done or failed, or with 202 if it is still enqueued when the timeout elapses.")
+                    .response::<200, Json<db::FinishedTask>>()
+                    .response_with::<202, (), _>(|r| r.description("the task is not finished yet"))
+                    .response_with::<404, (), _>(|r| r.description("the task does not exist"))
+                    .response_with::<410, (), _>(|r| r.description("the task was removed while waiting, without finishing"))
+            }),
+        )
         .api_route("/api/v1/tasks/enqueued", get(get_enqueued_tasks))
+        .api_route("/api/v1/tasks/ongoing", get(get_ongoing_tasks))
+        .api_route("/api/v1/concurrency-limits", get(get_concurrency_limits))
+        .api_route(
+            "/api/v1/concurrency-limits/:scope/:key",
+            put_with(set_concurrency_limit, |r| {
+                r.summary("/api/v1/concurrency-limits/:scope/:key")
+                    .description("limit how many tasks of a job, or for an endpoint host, can run at once across all workers.")
+                    .response_with::<204, (), _>(|r| r.description("the limit was saved"))
+                    .response_with::<400, String, _>(|r| r.description("maxConcurrency is less than 1"))
+            })
+            .delete_with(remove_concurrency_limit, |r| {
+                r.summary("/api/v1/concurrency-limits/:scope/:key")
+                    .description("remove a concurrency limit.")
+                    .response_with::<204, (), _>(|r| r.description("the limit was removed"))
+                    .response_with::<404, (), _>(|r| r.description("there is no such limit"))
+            }),
+        )
         .api_route("/api/v1/tasks/finished", get(get_finished_tasks))
-        .finish_api_with(api, |api| api.default_response::<String>())
 }
 
 fn generate_nanoid() -> String {
@@ -151,4 +626,24 @@ struct NewTaskBody {
     run_at: Option<chrono::DateTime<chrono::Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_retries: Option<usize>,
+    /// An endpoint to notify if the task is cancelled while it is running,
+    /// so it can stop working on it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cancel_endpoint: Option<url::Url>,
+    /// How long to wait for the endpoint to respond, e.g. "30s" or "10m", from 1ms to 24h.
+    /// Overrides the job and server defaults. A timed out invocation is retried.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<String>,
+    /// Headers to send with every invocation, e.g. to authenticate with the endpoint.
+    /// They are encrypted at rest, so the server needs a header encryption key, and are never
+    /// returned by the API. Hop-by-hop, `Host`, `Content-*`, `Webhook-*` and `X-Pointguard-*`
+    /// headers are reserved. Credentials shared by many tasks are better configured
+    /// on the server by endpoint URL prefix.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    headers: HashMap<String, String>,
+    /// How to read the endpoint's response. Use `lenient` for endpoints that
+    /// don't know about pointguard, so any 2xx response counts as a success.
+    /// Defaults to the job's response mode on the server, which is `strict` unless configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mode: Option<db::ResponseMode>,
 }
@@ -0,0 +1,62 @@
+use db::postgres::PgPool;
+use pointguard_engine_postgres as db;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Fans out "task finished" notifications from Postgres to the in-process
+/// requests waiting on them, so we hold a single LISTEN connection no matter
+/// how many clients are waiting.
+#[derive(Clone)]
+pub(crate) struct TaskWaiter {
+    finished_tx: broadcast::Sender<Finished>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Finished {
+    Task(i64),
+    /// The listener (re)connected, and tasks that finished while it was
+    /// disconnected weren't notified, so waiters should look again
+    Missed,
+}
+
+impl TaskWaiter {
+    pub(crate) fn spawn(pool: PgPool) -> Self {
+        let (finished_tx, _) = broadcast::channel(1024);
+        tokio::spawn(forward_notifications(pool, finished_tx.clone()));
+        Self { finished_tx }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Finished> {
+        self.finished_tx.subscribe()
+    }
+}
+
+async fn forward_notifications(pool: PgPool, finished_tx: broadcast::Sender<Finished>) {
+    loop {
+        let mut listener = match db::FinishedTaskListener::new(&pool).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("can't listen to finished tasks: {err}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        // no receivers is fine, it just means no one is waiting
+        _ = finished_tx.send(Finished::Missed);
+
+        loop {
+            match listener.take().await {
+                Ok(Some(payload)) => _ = finished_tx.send(Finished::Task(payload.id)),
+                Ok(None) => {
+                    tracing::warn!("lost the finished tasks listener connection");
+                    break;
+                }
+                Err(err) => {
+                    tracing::error!("finished tasks listener failed: {err}");
+                    break;
+                }
+            }
+        }
+    }
+}
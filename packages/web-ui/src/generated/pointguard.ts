@@ -31,6 +31,27 @@ export interface paths {
             }, {
               /** @enum {string} */
               type: "taskFinished";
+            }, {
+              /** @enum {string} */
+              type: "taskCancelled";
+            }, {
+              /** @enum {string} */
+              type: "taskRescheduled";
+            }, {
+              /** Format: int64 */
+              id: number;
+              message?: string | null;
+              /** Format: float */
+              percent?: number | null;
+              /** @enum {string} */
+              type: "taskProgress";
+            }, {
+              /** Format: uint */
+              concurrency: number;
+              /** Format: uint */
+              running: number;
+              /** @enum {string} */
+              type: "workerUtilization";
             }]>;
           };
         };
@@ -61,11 +82,79 @@ export interface paths {
       };
     };
   };
+  "/api/v1/health": {
+    /**
+     * /api/v1/health
+     * @description whether this server can pick up new tasks as they are enqueued.
+     */
+    get: {
+      responses: {
+        200: {
+          content: {
+            "application/json": {
+              taskListener: {
+                connected: boolean;
+                /** @description Why the listener last disconnected */
+                lastError?: string | null;
+                /**
+                 * Format: uint64
+                 * @description How many times the listener reconnected after the first connection
+                 */
+                reconnects: number;
+                /**
+                 * Format: date-time
+                 * @description When the listener last connected or disconnected
+                 */
+                since?: string | null;
+              };
+            };
+          };
+        };
+        /** @description the task listener is disconnected from the database */
+        503: {
+          content: {
+            "application/json": {
+              taskListener: {
+                connected: boolean;
+                /** @description Why the listener last disconnected */
+                lastError?: string | null;
+                /**
+                 * Format: uint64
+                 * @description How many times the listener reconnected after the first connection
+                 */
+                reconnects: number;
+                /**
+                 * Format: date-time
+                 * @description When the listener last connected or disconnected
+                 */
+                since?: string | null;
+              };
+            };
+          };
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
   "/api/v1/tasks": {
+    /**
+     * /api/v1/tasks
+     * @description enqueue a task. Responds with its ID, or with the ID of the existing task with the same job name, name and endpoint.
+     */
     post: {
       requestBody: {
         content: {
           "application/json": {
+            /**
+             * Format: uri
+             * @description An endpoint to notify if the task is cancelled while it is running, so it can stop working on it.
+             */
+            cancelEndpoint?: string | null;
             /** @description The data that will be passed on execution. */
             data?: unknown;
             /**
@@ -73,17 +162,25 @@ export interface paths {
              * @description The pointguard endpoint that'll be invoked
              */
             endpoint: string;
+            /** @description Headers to send with every invocation, e.g. to authenticate with the endpoint. They are encrypted at rest, so the server needs a header encryption key, and are never returned by the API. Hop-by-hop, `Host`, `Content-*`, `Webhook-*` and `X-Pointguard-*` headers are reserved. Credentials shared by many tasks are better configured on the server by endpoint URL prefix. */
+            headers?: {
+              [key: string]: string | undefined;
+            };
             /** @description The job name. This is used to know which function to invoke. */
             jobName: string;
             /** Format: uint */
             maxRetries?: number | null;
             /** @description A name for the task. If not provided, a random name will be generated. This is useful to throttle tasks of the same type. */
             name?: string | null;
+            /** @description How to read the endpoint's response. Use `lenient` for endpoints that don't know about pointguard, so any 2xx response counts as a success. Defaults to the job's response mode on the server, which is `strict` unless configured. */
+            responseMode?: "strict" | "lenient" | null;
             /**
              * Format: date-time
              * @description When to run the task. If not provided, it'll run as soon as possible.
              */
             runAt?: string | null;
+            /** @description How long to wait for the endpoint to respond, e.g. "30s" or "10m", from 1ms to 24h. Overrides the job and server defaults. A timed out invocation is retried. */
+            timeout?: string | null;
           };
         };
       };
@@ -93,6 +190,12 @@ export interface paths {
             "application/json": number;
           };
         };
+        /** @description the timeout or a header is invalid or reserved, the server can't encrypt headers, or the endpoint is not allowed */
+        400: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
         /** @description plain text */
         default: {
           content: {
@@ -103,6 +206,10 @@ export interface paths {
     };
   };
   "/api/v1/tasks/{id}/cancel": {
+    /**
+     * /api/v1/tasks/:id/cancel
+     * @description cancel a task. An enqueued task is removed, and a running task is stopped and recorded as cancelled.
+     */
     post: {
       parameters: {
         path: {
@@ -136,6 +243,247 @@ export interface paths {
       };
     };
   };
+  "/api/v1/tasks/{id}/complete": {
+    /**
+     * /api/v1/tasks/:id/complete
+     * @description report that an accepted task succeeded. Authenticated with the attempt token the endpoint was invoked with, as `Authorization: Bearer <attemptToken>`.
+     */
+    post: {
+      parameters: {
+        path: {
+          id: number;
+        };
+      };
+      responses: {
+        /** @description the task is done */
+        204: {
+          content: never;
+        };
+        /** @description the attempt token is missing */
+        401: {
+          content: never;
+        };
+        /** @description there is no such attempt, or the task was cancelled */
+        404: {
+          content: never;
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
+  "/api/v1/tasks/{id}/fail": {
+    /**
+     * /api/v1/tasks/:id/fail
+     * @description report that an accepted task failed. It is retried if it is retriable and has retries left. Authenticated like `/complete`.
+     */
+    post: {
+      parameters: {
+        path: {
+          id: number;
+        };
+      };
+      requestBody: {
+        content: {
+          "application/json": {
+            /** @description The reason why it failed */
+            reason: string;
+            /** @description Whether or not this task is retriable. Defaults to true. */
+            retriable?: boolean | null;
+          };
+        };
+      };
+      responses: {
+        /** @description the failure was recorded */
+        204: {
+          content: never;
+        };
+        /** @description the attempt token is missing */
+        401: {
+          content: never;
+        };
+        /** @description there is no such attempt, or the task was cancelled */
+        404: {
+          content: never;
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
+  "/api/v1/tasks/{id}/progress": {
+    /**
+     * /api/v1/tasks/:id/progress
+     * @description report the progress of a running task. It shows up in the ongoing tasks and the events stream. Authenticated like `/complete`.
+     */
+    post: {
+      parameters: {
+        path: {
+          id: number;
+        };
+      };
+      requestBody: {
+        content: {
+          "application/json": {
+            /** @description What the task is doing */
+            message?: string | null;
+            /**
+             * Format: float
+             * @description How far along the task is, from 0 to 100
+             */
+            percent?: number | null;
+          };
+        };
+      };
+      responses: {
+        /** @description the progress was stored */
+        204: {
+          content: never;
+        };
+        /** @description percent is not between 0 and 100 */
+        400: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+        /** @description the attempt token is missing */
+        401: {
+          content: never;
+        };
+        /** @description there is no such attempt */
+        404: {
+          content: never;
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
+  "/api/v1/tasks/{id}/heartbeat": {
+    /**
+     * /api/v1/tasks/:id/heartbeat
+     * @description extend the lease of an accepted task that is still being worked on. Authenticated like `/complete`.
+     */
+    post: {
+      parameters: {
+        path: {
+          id: number;
+        };
+      };
+      requestBody: {
+        content: {
+          "application/json": {
+            /**
+             * Format: uint64
+             * @description How long from now until the task is retried, up to 7 days. Defaults to the lease the task was accepted with.
+             */
+            leaseSeconds?: number | null;
+          };
+        };
+      };
+      responses: {
+        /** @description the lease was extended */
+        204: {
+          content: never;
+        };
+        /** @description the body is invalid, or leaseSeconds is more than 7 days */
+        400: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+        /** @description the attempt token is missing */
+        401: {
+          content: never;
+        };
+        /** @description there is no such accepted attempt, or the task was cancelled */
+        404: {
+          content: never;
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
+  "/api/v1/tasks/{id}/wait": {
+    /**
+     * /api/v1/tasks/:id/wait
+     * @description wait for a task to reach a terminal state. Responds with the finished task as soon as it is done or failed, or with 202 if it is still enqueued when the timeout elapses.
+     */
+    get: {
+      parameters: {
+        query?: {
+          /** @description How long to wait for the task to finish, e.g. "30s" or "2m". Defaults to 30 seconds and is capped at 5 minutes. */
+          timeout?: string | null;
+        };
+        path: {
+          id: number;
+        };
+      };
+      responses: {
+        200: {
+          content: {
+            "application/json": {
+              /** @description Whether the task was cancelled while it was running */
+              cancelled: boolean;
+              /** Format: date-time */
+              createdAt: string;
+              data: unknown;
+              endpoint: string;
+              errorMessage?: string | null;
+              /** Format: int64 */
+              id: number;
+              jobName: string;
+              name: string;
+              /** Format: int32 */
+              retries: number;
+              /** Format: date-time */
+              startedAt: string;
+              /**
+               * Format: int64
+               * @description The ID the task had while it was enqueued
+               */
+              taskId?: number | null;
+            };
+          };
+        };
+        /** @description the task is not finished yet */
+        202: {
+          content: never;
+        };
+        /** @description the task does not exist */
+        404: {
+          content: never;
+        };
+        /** @description the task was removed while waiting, without finishing */
+        410: {
+          content: never;
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
   "/api/v1/tasks/enqueued": {
     get: {
       responses: {
@@ -146,6 +494,8 @@ export interface paths {
                 createdAt: string;
                 data: unknown;
                 endpoint: string;
+                /** @description The names of the headers sent with every invocation. Their values are never returned. */
+                headerNames: (string)[];
                 /** Format: int64 */
                 id: number;
                 jobName: string;
@@ -169,12 +519,174 @@ export interface paths {
       };
     };
   };
+  "/api/v1/tasks/ongoing": {
+    get: {
+      responses: {
+        200: {
+          content: {
+            "application/json": ({
+                /** @description Identifies the current attempt, like the `attemptId` the endpoint was invoked with */
+                attemptId?: string | null;
+                /** Format: date-time */
+                createdAt: string;
+                data: unknown;
+                endpoint: string;
+                /** @description The names of the headers sent with every invocation. Their values are never returned. */
+                headerNames: (string)[];
+                /** Format: int64 */
+                id: number;
+                jobName: string;
+                /**
+                 * Format: date-time
+                 * @description When the task is retried, if it was accepted by its endpoint
+                 */
+                leaseExpiresAt?: string | null;
+                /** Format: int32 */
+                maxRetries: number;
+                name: string;
+                /** @description What the task is doing, as reported by the endpoint */
+                progressMessage?: string | null;
+                /**
+                 * Format: float
+                 * @description How far along the task is, from 0 to 100, as reported by the endpoint
+                 */
+                progressPercent?: number | null;
+                /** Format: date-time */
+                progressUpdatedAt?: string | null;
+                /** Format: int32 */
+                retryCount: number;
+                /** Format: date-time */
+                startedAt: string;
+                workerId: string;
+              })[];
+          };
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
+  "/api/v1/concurrency-limits": {
+    get: {
+      responses: {
+        200: {
+          content: {
+            "application/json": ({
+                /** Format: date-time */
+                createdAt: string;
+                key: string;
+                /** Format: int32 */
+                maxConcurrency: number;
+                /**
+                 * Format: int64
+                 * @description How many matching tasks are running, or accepted by their endpoint, right now
+                 */
+                running: number;
+                /** @description What a [`ConcurrencyLimit`] key is matched against */
+                scope: "jobName" | "endpointHost";
+                /** Format: date-time */
+                updatedAt: string;
+              })[];
+          };
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
+  "/api/v1/concurrency-limits/{scope}/{key}": {
+    /**
+     * /api/v1/concurrency-limits/:scope/:key
+     * @description limit how many tasks of a job, or for an endpoint host, can run at once across all workers.
+     */
+    put: {
+      parameters: {
+        path: {
+          /** @description The job name or endpoint host */
+          key: string;
+          /** @description What a [`ConcurrencyLimit`] key is matched against */
+          scope: "jobName" | "endpointHost";
+        };
+      };
+      requestBody: {
+        content: {
+          "application/json": {
+            /**
+             * Format: int32
+             * @description How many matching tasks can be running at once
+             */
+            maxConcurrency: number;
+          };
+        };
+      };
+      responses: {
+        /** @description the limit was saved */
+        204: {
+          content: never;
+        };
+        /** @description maxConcurrency is less than 1 */
+        400: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+    /**
+     * /api/v1/concurrency-limits/:scope/:key
+     * @description remove a concurrency limit.
+     */
+    delete: {
+      parameters: {
+        path: {
+          /** @description The job name or endpoint host */
+          key: string;
+          /** @description What a [`ConcurrencyLimit`] key is matched against */
+          scope: "jobName" | "endpointHost";
+        };
+      };
+      responses: {
+        /** @description the limit was removed */
+        204: {
+          content: never;
+        };
+        /** @description there is no such limit */
+        404: {
+          content: never;
+        };
+        /** @description plain text */
+        default: {
+          content: {
+            "text/plain; charset=utf-8": unknown;
+          };
+        };
+      };
+    };
+  };
   "/api/v1/tasks/finished": {
     get: {
       parameters: {
         query?: {
           limit?: number | null;
           page?: number | null;
+          /** @description Only include tasks that finished at or after this time */
+          since?: string | null;
+          /** @description Only include tasks that finished before this time */
+          until?: string | null;
         };
       };
       responses: {
@@ -182,6 +694,8 @@ export interface paths {
           content: {
             "application/json": {
               items: ({
+                  /** @description Whether the task was cancelled while it was running */
+                  cancelled: boolean;
                   /** Format: date-time */
                   createdAt: string;
                   data: unknown;
@@ -195,6 +709,11 @@ export interface paths {
                   retries: number;
                   /** Format: date-time */
                   startedAt: string;
+                  /**
+                   * Format: int64
+                   * @description The ID the task had while it was enqueued
+                   */
+                  taskId?: number | null;
                 })[];
               /** Format: uint */
               page: number;
@@ -216,10 +735,45 @@ export interface paths {
 
 export interface webhooks {
   "executeTask": {
+    /**
+     * @description Invokes a task. When the server is started with `--signing-secret`, requests are signed following [Standard Webhooks](https://www.standardwebhooks.com), so any of its libraries can verify them. To verify by hand:
+     * 
+     * 1. Reject the request if `webhook-timestamp` is more than a few minutes away from now.
+     * 2. Compute the HMAC-SHA256 of `{webhook-id}.{webhook-timestamp}.{raw body}`, keyed with the base64-decoded part of the secret after `whsec_`.
+     * 3. Accept the request if the base64 encoded result matches any of the `v1,` entries of `webhook-signature`, comparing in constant time.
+     * 
+     * Every configured secret signs the request, so a secret can be rotated by adding the new one, updating the endpoints, and then removing the old one.
+     */
     post: {
+      parameters: {
+        header?: {
+          /** @description When pointguard stops waiting for the response, as an RFC 3339 timestamp. A timed out invocation is retried. */
+          "x-pointguard-deadline"?: string;
+          /** @description The `taskId` of the payload. */
+          "x-pointguard-task-id"?: number;
+          /** @description The `name` of the payload. Left out when the name can't be sent as a header. */
+          "x-pointguard-task-name"?: string;
+          /** @description The `attemptId` of the payload. */
+          "x-pointguard-attempt-id"?: string;
+          /** @description The `scheduledFor` of the payload, as an RFC 3339 timestamp. */
+          "x-pointguard-scheduled-for"?: string;
+          /** @description The `startedAt` of the payload, as an RFC 3339 timestamp. */
+          "x-pointguard-started-at"?: string;
+          /** @description The `taskId` of the payload, the same for every attempt so retries can be deduplicated. Only sent when the server has signing secrets. */
+          "webhook-id"?: string;
+          /** @description When the invocation was signed, in seconds since the Unix epoch. Only sent when the server has signing secrets. */
+          "webhook-timestamp"?: number;
+          /** @description Space separated `v1,<base64 signature>` entries, one per signing secret. Only sent when the server has signing secrets. */
+          "webhook-signature"?: string;
+        };
+      };
       requestBody: {
         content: {
           "application/json": {
+            /** @description Identifies this attempt. Retries of the task are new attempts. */
+            attemptId: string;
+            /** @description Authenticates reports on this attempt, like `POST /api/v1/tasks/:id/complete`, sent as `Authorization: Bearer <attemptToken>` */
+            attemptToken: string;
             /**
              * Format: date-time
              * @description The time when this task was enqueued at
@@ -234,11 +788,28 @@ export interface webhooks {
              * @description The maximum amount of times we can retry this task
              */
             maxRetries: number;
+            /** @description The name of the task, unique for its job and endpoint */
+            name: string;
             /**
              * Format: int32
              * @description The amount of times we retried this task
              */
             retryCount: number;
+            /**
+             * Format: date-time
+             * @description When the task was due to run. Earlier than `startedAt` when the workers were busy.
+             */
+            scheduledFor: string;
+            /**
+             * Format: date-time
+             * @description When this attempt started
+             */
+            startedAt: string;
+            /**
+             * Format: int64
+             * @description The ID of the task
+             */
+            taskId: number;
           };
         };
       };
@@ -247,6 +818,32 @@ export interface webhooks {
           content: {
             "application/json": OneOf<[{
               success: Record<string, never>;
+            }, {
+              accepted: {
+                /**
+                 * Format: uint64
+                 * @description How long until the task is retried, unless it is reported on or extended with `POST /api/v1/tasks/:id/heartbeat`
+                 */
+                leaseSeconds: number;
+              };
+            }, {
+              /** @description When a rescheduled task runs again */
+              reschedule: ({
+                /** @description Replaces the task's input for the next invocation */
+                data?: unknown;
+              }) & (OneOf<[{
+                /**
+                 * Format: date-time
+                 * @description When to run the task again
+                 */
+                runAt: string;
+              }, {
+                /**
+                 * Format: uint64
+                 * @description How long from now to run the task again
+                 */
+                delaySeconds: number;
+              }]>);
             }, {
               failure: {
                 /** @description The reason why it failed */
@@ -269,6 +866,10 @@ export interface components {
   schemas: {
     /** InvokedTaskPayload */
     InvokedTaskPayload: {
+      /** @description Identifies this attempt. Retries of the task are new attempts. */
+      attemptId: string;
+      /** @description Authenticates reports on this attempt, like `POST /api/v1/tasks/:id/complete`, sent as `Authorization: Bearer <attemptToken>` */
+      attemptToken: string;
       /**
        * Format: date-time
        * @description The time when this task was enqueued at
@@ -283,11 +884,28 @@ export interface components {
        * @description The maximum amount of times we can retry this task
        */
       maxRetries: number;
+      /** @description The name of the task, unique for its job and endpoint */
+      name: string;
       /**
        * Format: int32
        * @description The amount of times we retried this task
        */
       retryCount: number;
+      /**
+       * Format: date-time
+       * @description When the task was due to run. Earlier than `startedAt` when the workers were busy.
+       */
+      scheduledFor: string;
+      /**
+       * Format: date-time
+       * @description When this attempt started
+       */
+      startedAt: string;
+      /**
+       * Format: int64
+       * @description The ID of the task
+       */
+      taskId: number;
     };
   };
   responses: never;